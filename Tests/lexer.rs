@@ -359,3 +359,130 @@ fn test_lexer_mixed_whitespace() {
 
     assert_eq!(tokens, expected);
 }
+
+#[test]
+fn test_lexer_line_macro_follows_line_markers() {
+    let mut lexer = Lexer::new("__LINE__\n#line 40 \"other.h\"\n__LINE__\n\n__LINE__");
+    let tokens = lexer.tokenize();
+
+    assert_eq!(tokens, vec![Token::Num(1), Token::Num(40), Token::Num(42), Token::EOF]);
+    assert_eq!(lexer.file, Some("other.h".into()));
+}
+
+#[test]
+fn test_lexer_escape_sequences() {
+    let source = r#"'\0' '\x41' '\101' '\a' '\b' '\f' '\v' '\?' "a\tb\x7F\177\0z""#;
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.tokenize();
+
+    let expected = vec![
+        Token::Num(0),
+        Token::Num(65),
+        Token::Num(65),
+        Token::Num(7),
+        Token::Num(8),
+        Token::Num(12),
+        Token::Num(11),
+        Token::Num('?' as i64),
+        Token::Str("a\tb\x7f\x7f\0z".into()),
+        Token::EOF,
+    ];
+
+    assert_eq!(tokens, expected);
+    assert!(lexer.errors.is_empty());
+}
+
+#[test]
+fn test_lexer_malformed_escape_sequences() {
+    let mut lexer = Lexer::new("'\\q'\n\"\\x\" '\\x100' \"\\777\"");
+    lexer.tokenize();
+
+    assert_eq!(lexer.errors, vec![
+        "line 1: Unknown escape sequence '\\q'".to_string(),
+        "line 2: \\x used with no following hex digits".to_string(),
+        "line 2: Hex escape sequence out of range: '\\x100'".to_string(),
+        "line 2: Octal escape sequence out of range: '\\777'".to_string(),
+    ]);
+}
+
+#[test]
+fn test_lexer_integer_suffixes_and_large_constants() {
+    let source = "10L 0xFFu 017ul 5LLU 0uLL 9223372036854775807 0xFFFFFFFFFFFFFFFF 18446744073709551615u";
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.tokenize();
+
+    let expected = vec![
+        Token::Num(10),
+        Token::Num(255),
+        Token::Num(15),
+        Token::Num(5),
+        Token::Num(0),
+        Token::Num(i64::MAX),
+        Token::Num(-1),
+        Token::Num(-1),
+        Token::EOF,
+    ];
+
+    assert_eq!(tokens, expected);
+    assert!(lexer.errors.is_empty());
+}
+
+#[test]
+fn test_lexer_malformed_integer_constants() {
+    let mut lexer = Lexer::new("10lL 12abc 0x 089 9223372036854775808 0x10000000000000000");
+    lexer.tokenize();
+
+    assert_eq!(lexer.errors, vec![
+        "line 1: Invalid suffix 'lL' on integer constant".to_string(),
+        "line 1: Invalid suffix 'abc' on integer constant".to_string(),
+        "line 1: Invalid integer constant '0x'".to_string(),
+        "line 1: Invalid digit '8' in octal constant".to_string(),
+        "line 1: Integer constant '9223372036854775808' is too large for its type".to_string(),
+        "line 1: Integer constant '0x10000000000000000' is too large for its type".to_string(),
+    ]);
+}
+
+#[test]
+fn test_lexer_floating_constants() {
+    let mut lexer = Lexer::new("1.5 .25 2. 1e-3 6.02E+23 0.1f 3.5L 0x1e+1 x.y");
+    let tokens = lexer.tokenize();
+
+    let expected = vec![
+        Token::FNum(1.5),
+        Token::FNum(0.25),
+        Token::FNum(2.0),
+        Token::FNum(1e-3),
+        Token::FNum(6.02e23),
+        Token::FNum(0.1f32 as f64),
+        Token::FNum(3.5),
+        Token::Num(0x1e), // an exponent sign does not follow hex digits
+        Token::Add,
+        Token::Num(1),
+        Token::Id("x".to_string()),
+        Token::Dot,
+        Token::Id("y".to_string()),
+        Token::EOF,
+    ];
+
+    assert_eq!(tokens, expected);
+    assert!(lexer.errors.is_empty());
+}
+
+#[test]
+fn test_lexer_malformed_floating_constants() {
+    let mut lexer = Lexer::new("1.5.2 1e 2.5x");
+    lexer.tokenize();
+
+    assert_eq!(lexer.errors, vec![
+        "line 1: Invalid floating constant '1.5.2'".to_string(),
+        "line 1: Invalid floating constant '1e'".to_string(),
+        "line 1: Invalid floating constant '2.5x'".to_string(),
+    ]);
+}
+
+#[test]
+fn test_lexer_rejects_line_zero() {
+    let mut lexer = Lexer::new("#line 0\nx");
+    assert_eq!(lexer.tokenize(), vec![Token::Id("x".to_string()), Token::EOF]);
+    assert_eq!(lexer.errors, vec!["line 1: Line number out of range in #line directive".to_string()]);
+}
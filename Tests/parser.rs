@@ -1,6 +1,6 @@
 use c4::lexer::*;
 use c4::parser::*;
-use c4::vm::*;
+use c4::types::*;
 
 #[test]
 fn test_parse_simple_decl() {
@@ -16,7 +16,7 @@ fn test_parse_simple_decl() {
 
     let expected = ASTNode::Block(vec![
         ASTNode::Decl {
-            typename: Token::Int,
+            typename: Type::Int,
             name: "x".into(),
         },
     ]);
@@ -40,7 +40,7 @@ fn test_parse_decl_assign() {
 
     let expected = ASTNode::Block(vec![
         ASTNode::DeclAssign {
-            typename: Token::Int,
+            typename: Type::Int,
             name: "x".into(),
             value: Box::new(ASTNode::Num(10)),
         },
@@ -71,16 +71,16 @@ fn test_parse_multiple_decls() {
 
     let expected = ASTNode::Block(vec![
         ASTNode::DeclAssign {
-            typename: Token::Int,
+            typename: Type::Int,
             name: "x".into(),
             value: Box::new(ASTNode::Num(1)),
         },
         ASTNode::Decl {
-            typename: Token::Int,
+            typename: Type::Int,
             name: "y".into(),
         },
         ASTNode::DeclAssign {
-            typename: Token::Int,
+            typename: Type::Int,
             name: "z".into(),
             value: Box::new(ASTNode::Num(3)),
         },
@@ -221,7 +221,7 @@ fn test_parse_function_decl() {
     let ast = parser.parse_program();
 
     let expected = ASTNode::FuncDef {
-        return_type: Token::Int,
+        return_type: Type::Int,
         name: "main".into(),
         params: vec![],
         body: vec![
@@ -311,16 +311,16 @@ fn test_mixed_multi_decls() {
 
     let expected = ASTNode::Block(vec![
         ASTNode::DeclAssign {
-            typename: Token::Int,
+            typename: Type::Int,
             name: "a".into(),
             value: Box::new(ASTNode::Num(1)),
         },
         ASTNode::Decl {
-            typename: Token::Int,
+            typename: Type::Int,
             name: "b".into(),
         },
         ASTNode::DeclAssign {
-            typename: Token::Int,
+            typename: Type::Int,
             name: "c".into(),
             value: Box::new(ASTNode::Num(3)),
         },
@@ -373,3 +373,465 @@ fn test_while_with_block() {
 
     assert_eq!(ast, vec![expected]);
 }
+
+#[test]
+fn test_parse_array_decl_with_initializer() {
+    let tokens = vec![
+        Token::Int,
+        Token::Id("a".into()),
+        Token::LBracket,
+        Token::RBracket,
+        Token::Assign,
+        Token::LBrace,
+        Token::Num(1),
+        Token::Comma,
+        Token::Num(2),
+        Token::RBrace,
+        Token::Semicolon,
+        Token::EOF,
+    ];
+
+    let mut parser = Parser::new(tokens);
+    let ast = parser.parse_program();
+
+    let expected = ASTNode::Block(vec![
+        ASTNode::DeclAssign {
+            typename: Type::Array(Box::new(Type::Int), 2),
+            name: "a".into(),
+            value: Box::new(ASTNode::InitList(vec![
+                ASTNode::Num(1),
+                ASTNode::Num(2),
+            ])),
+        },
+    ]);
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_index_assign() {
+    let tokens = vec![
+        Token::Id("a".into()),
+        Token::LBracket,
+        Token::Id("i".into()),
+        Token::RBracket,
+        Token::Assign,
+        Token::Num(5),
+        Token::Semicolon,
+        Token::EOF,
+    ];
+
+    let mut parser = Parser::new(tokens);
+    let ast = parser.parse_program();
+
+    let expected = ASTNode::MemAssign {
+        target: Box::new(ASTNode::Index {
+            array: Box::new(ASTNode::Id("a".into())),
+            index: Box::new(ASTNode::Id("i".into())),
+        }),
+        value: Box::new(ASTNode::Num(5)),
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_pointer_types() {
+    let tokens = vec![
+        Token::Char,
+        Token::Mul,
+        Token::Mul,
+        Token::Id("argv".into()),
+        Token::Comma,
+        Token::Id("c".into()),
+        Token::Semicolon,
+        Token::EOF,
+    ];
+
+    let mut parser = Parser::new(tokens);
+    let ast = parser.parse_program();
+
+    let expected = ASTNode::Block(vec![
+        ASTNode::Decl {
+            typename: Type::Char.pointer_to().pointer_to(),
+            name: "argv".into(),
+        },
+        ASTNode::Decl {
+            typename: Type::Char,
+            name: "c".into(),
+        },
+    ]);
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_function_pointer_params() {
+    let source = "int *find(int *a, char s[]) { return a; }";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    match &ast[0] {
+        ASTNode::FuncDef { return_type, params, .. } => {
+            assert_eq!(*return_type, Type::Int.pointer_to());
+            assert_eq!(params, &vec![
+                (Type::Int.pointer_to(), "a".to_string()),
+                (Type::Char.pointer_to(), "s".to_string()),
+            ]);
+        }
+        other => panic!("Expected a function definition, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_sizeof() {
+    let source = "sizeof(char *) + sizeof x;";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let expected = ASTNode::BinaryOp {
+        op: Token::Add,
+        left: Box::new(ASTNode::SizeofType(Type::Char.pointer_to())),
+        right: Box::new(ASTNode::SizeofExpr(Box::new(ASTNode::Id("x".into())))),
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_cast_versus_grouping() {
+    let source = "(char *)p + (x);";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let expected = ASTNode::BinaryOp {
+        op: Token::Add,
+        left: Box::new(ASTNode::Cast {
+            typename: Type::Char.pointer_to(),
+            expr: Box::new(ASTNode::Id("p".into())),
+        }),
+        right: Box::new(ASTNode::Id("x".into())),
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_enum_constants() {
+    let source = "enum { A, B = 10, C, D = -1, E }; int x = C + E;";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let expected = ASTNode::Block(vec![ASTNode::DeclAssign {
+        typename: Type::Int,
+        name: "x".into(),
+        value: Box::new(ASTNode::BinaryOp {
+            op: Token::Add,
+            left: Box::new(ASTNode::Num(11)),
+            right: Box::new(ASTNode::Num(0)),
+        }),
+    }]);
+
+    assert_eq!(ast, vec![ASTNode::Block(vec![]), expected]);
+}
+
+#[test]
+fn test_parse_duplicate_enumerator() {
+    let source = "enum { A, B };\nenum { A };\nint n;\nenum { C = n };";
+    assert_eq!(parse_errors(source), vec![
+        "line 2: Redeclaration of enumerator 'A'".to_string(),
+        "line 4: Enumerator value for 'C' is not an integer constant".to_string(),
+    ]);
+}
+
+#[test]
+fn test_parse_member_access() {
+    let source = "p->next->value = s.x;";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    // p->next is parsed as (*p).next
+    let deref_member = |expr: ASTNode, name: &str| ASTNode::Member {
+        expr: Box::new(ASTNode::UnaryOp { op: Token::Mul, expr: Box::new(expr) }),
+        name: name.into(),
+    };
+    let expected = ASTNode::MemAssign {
+        target: Box::new(deref_member(deref_member(ASTNode::Id("p".into()), "next"), "value")),
+        value: Box::new(ASTNode::Member {
+            expr: Box::new(ASTNode::Id("s".into())),
+            name: "x".into(),
+        }),
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_struct_layout() {
+    let source = "struct s { char c; int i; char d[3]; } v; union { char c; int i; } w;";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let declared_type = |node: &ASTNode| match node {
+        ASTNode::Block(decls) => match &decls[0] {
+            ASTNode::Decl { typename, .. } => typename.clone(),
+            other => panic!("expected a declaration, got {:?}", other),
+        },
+        other => panic!("expected a declaration, got {:?}", other),
+    };
+
+    // Members are aligned to their own size and the struct to its widest member
+    let Type::Struct(s) = declared_type(&ast[0]) else { panic!("expected a struct") };
+    let offsets: Vec<usize> = s.members().iter().map(|member| member.offset).collect();
+    assert_eq!(offsets, vec![0, 8, 16]);
+    assert_eq!(declared_type(&ast[0]).size(), 24);
+
+    let Type::Struct(u) = declared_type(&ast[1]) else { panic!("expected a union") };
+    assert!(u.members().iter().all(|member| member.offset == 0));
+    assert_eq!(declared_type(&ast[1]).size(), 8);
+}
+
+#[test]
+fn test_parse_struct_definition_errors() {
+    let source = "struct s { int a; };\nstruct s { int b; };\nunion s *p;\nstruct t { int a; char a; };\nstruct u;\nstruct v { struct u m; };";
+    assert_eq!(parse_errors(source), vec![
+        "line 2: Redefinition of 'struct s'".to_string(),
+        "line 3: 's' defined as wrong kind of tag".to_string(),
+        "line 4: Duplicate member 'a' in 'struct t'".to_string(),
+        "line 6: Member 'm' has incomplete type 'struct u'".to_string(),
+    ]);
+}
+
+#[test]
+fn test_parse_switch() {
+    let source = "switch (x) { case 1: case -2: y = 1; break; default: y = 2; }";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let assign = |value| ASTNode::Assign { name: "y".into(), value: Box::new(ASTNode::Num(value)) };
+    let expected = ASTNode::Switch {
+        expr: Box::new(ASTNode::Id("x".into())),
+        body: vec![
+            ASTNode::Case(1),
+            ASTNode::Case(-2),
+            assign(1),
+            ASTNode::Break,
+            ASTNode::Default,
+            assign(2),
+        ],
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_case_label_must_be_constant() {
+    let source = "int main() {\n    int x = 1;\n    switch (x) {\n    case x: return 1;\n    }\n}";
+    assert_eq!(parse_errors(source), vec![
+        "line 4: case label does not reduce to an integer constant".to_string(),
+    ]);
+}
+
+#[test]
+fn test_parse_typedef_names() {
+    let source = "typedef char *string; string s, *list; (string)x;";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let string = Type::Char.pointer_to();
+    let expected = vec![
+        ASTNode::Block(vec![]),
+        ASTNode::Block(vec![
+            ASTNode::Decl { typename: string.clone(), name: "s".into() },
+            ASTNode::Decl { typename: string.clone().pointer_to(), name: "list".into() },
+        ]),
+        ASTNode::Cast { typename: string, expr: Box::new(ASTNode::Id("x".into())) },
+    ];
+
+    assert_eq!(ast, expected);
+}
+
+#[test]
+fn test_parse_conflicting_typedefs() {
+    let source = "typedef int T;\ntypedef int T;\ntypedef char T;";
+    assert_eq!(parse_errors(source), vec![
+        "line 3: Conflicting types for typedef 'T'".to_string(),
+    ]);
+}
+
+#[test]
+fn test_parse_goto_and_label() {
+    let source = "int main() { goto end; x = 1; end: return x; }";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let expected = ASTNode::FuncDef {
+        return_type: Type::Int,
+        name: "main".into(),
+        params: vec![],
+        body: vec![
+            ASTNode::Goto("end".into()),
+            ASTNode::Assign { name: "x".into(), value: Box::new(ASTNode::Num(1)) },
+            ASTNode::Label("end".into()),
+            ASTNode::Return(Box::new(ASTNode::Id("x".into()))),
+        ],
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_comma_operator_in_for_header() {
+    let source = "for (i = 0, j = n; i < j; i++, j--) { }";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let comma = |left, right| ASTNode::BinaryOp { op: Token::Comma, left: Box::new(left), right: Box::new(right) };
+    let assign = |name: &str, value| ASTNode::Assign { name: name.into(), value: Box::new(value) };
+    let postfix = |op, name: &str| ASTNode::PostfixOp { op, expr: Box::new(ASTNode::Id(name.into())) };
+    let expected = ASTNode::ForLoop {
+        init: Some(Box::new(comma(assign("i", ASTNode::Num(0)), assign("j", ASTNode::Id("n".into()))))),
+        condition: Some(Box::new(ASTNode::BinaryOp {
+            op: Token::Lt,
+            left: Box::new(ASTNode::Id("i".into())),
+            right: Box::new(ASTNode::Id("j".into())),
+        })),
+        step: Some(Box::new(comma(postfix(Token::Inc, "i"), postfix(Token::Dec, "j")))),
+        body: vec![],
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_unary_minus_plus_and_complement() {
+    let source = "int a[2] = {-1, -x}; +~y;";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let unary = |op, expr| ASTNode::UnaryOp { op, expr: Box::new(expr) };
+    let expected = vec![
+        ASTNode::Block(vec![ASTNode::DeclAssign {
+            typename: Type::Array(Box::new(Type::Int), 2),
+            name: "a".into(),
+            // Negative literals are folded into constants
+            value: Box::new(ASTNode::InitList(vec![
+                ASTNode::Num(-1),
+                unary(Token::Sub, ASTNode::Id("x".into())),
+            ])),
+        }]),
+        unary(Token::Add, unary(Token::Tilde, ASTNode::Id("y".into()))),
+    ];
+
+    assert_eq!(ast, expected);
+}
+
+#[test]
+fn test_parse_nested_conditional_is_right_associative() {
+    let source = "a ? b : c ? d : e;";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let id = |name: &str| Box::new(ASTNode::Id(name.into()));
+    let expected = ASTNode::Cond {
+        cond: id("a"),
+        then_branch: id("b"),
+        else_branch: Box::new(ASTNode::Cond {
+            cond: id("c"),
+            then_branch: id("d"),
+            else_branch: id("e"),
+        }),
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_assignment_chain_is_right_associative() {
+    let source = "a = b = 3;";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let expected = ASTNode::Assign {
+        name: "a".into(),
+        value: Box::new(ASTNode::Assign {
+            name: "b".into(),
+            value: Box::new(ASTNode::Num(3)),
+        }),
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+#[test]
+fn test_parse_function_prototypes() {
+    let source = "int printf(char *format, ...); void free(void *); int main(void);";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let expected = vec![
+        ASTNode::FuncDecl {
+            return_type: Type::Int,
+            name: "printf".into(),
+            params: vec![(Type::Char.pointer_to(), "format".into())],
+            variadic: true,
+        },
+        ASTNode::FuncDecl {
+            return_type: Type::Void,
+            name: "free".into(),
+            params: vec![(Type::Void.pointer_to(), String::new())],
+            variadic: false,
+        },
+        ASTNode::FuncDecl {
+            return_type: Type::Int,
+            name: "main".into(),
+            params: vec![],
+            variadic: false,
+        },
+    ];
+
+    assert_eq!(ast, expected);
+}
+
+#[test]
+fn test_parse_adjacent_string_literals_are_concatenated() {
+    let source = "printf(\"%d \"\n    \"%d\\x41\" \"B\\n\", 1, 2);";
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+
+    let expected = ASTNode::FuncCall {
+        name: "printf".into(),
+        args: vec![ASTNode::Str("%d %dAB\n".into()), ASTNode::Num(1), ASTNode::Num(2)],
+    };
+
+    assert_eq!(ast, vec![expected]);
+}
+
+/// Parses a C source string, returning the diagnostics with their source lines
+fn parse_errors(source: &str) -> Vec<String> {
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.tokenize();
+    let mut parser = Parser::new(tokens);
+    parser.locations = lexer.locations;
+    parser.parse_program();
+    parser.errors
+}
+
+#[test]
+fn test_parse_malformed_array_declarations() {
+    let source = "int main() {\n    int a[];\n    int b[3;\n    char c[] = 5;\n}";
+    assert_eq!(parse_errors(source), vec![
+        "line 2: Array size missing for 'a'".to_string(),
+        "line 3: Expected ']' in array declarator, found Some(Semicolon)".to_string(),
+        "line 4: Invalid initializer for array 'c'".to_string(),
+    ]);
+}
+
+#[test]
+fn test_parse_array_size_must_be_constant() {
+    let source = "int main() {\n    int n = 3;\n    int a[n];\n    int b[1 - 2];\n}";
+    assert_eq!(parse_errors(source), vec![
+        "line 3: Array size is not an integer constant".to_string(),
+        "line 4: Size of array is negative".to_string(),
+    ]);
+}
@@ -0,0 +1,231 @@
+use c4::lexer::*;
+use c4::parser::*;
+use c4::preprocessor::*;
+use c4::sema::*;
+use c4::typeck::*;
+use c4::vm::builtins;
+use std::fs;
+use std::path::PathBuf;
+
+/// Preprocesses a C source string as if it came from "test.c"
+fn preprocess(source: &str) -> Result<String, Vec<String>> {
+    Preprocessor::new().preprocess(source, "test.c")
+}
+
+/// Tokenizes the preprocessed source, so tests don't depend on spacing
+fn expand(source: &str) -> Vec<Token> {
+    Lexer::new(&preprocess(source).unwrap()).tokenize()
+}
+
+/// Writes files into a fresh scratch directory and returns its path
+fn scratch_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("c4_preprocessor_{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_preprocessor_object_and_function_macros() {
+    let source = "
+        #define N 10
+        #define SQ(x) ((x) * (x))
+        #define MAX(a, b) ((a) > (b) ? (a) : (b))
+        int y = SQ(N + 1) - MAX(1,
+            SQ(2)) - -N;
+        #undef N
+        int N;
+    ";
+    let expected = "int y = ((10 + 1) * (10 + 1)) - ((1) > (((2) * (2))) ? (1) : (((2) * (2)))) - - 10; int N;";
+    assert_eq!(expand(source), Lexer::new(expected).tokenize());
+
+    // Directives and multi-line invocations keep the line numbers of the source
+    assert_eq!(preprocess(source).unwrap().matches('\n').count(), source.matches('\n').count());
+}
+
+#[test]
+fn test_preprocessor_stringify_and_paste() {
+    let source = "
+        #define STR(x) #x
+        #define CAT(a, b) a ## b
+        #define XCAT(a, b) CAT(a, b)
+        #define ID(x) x
+        #define F ID
+        #define LOOP for (;;) LOOP
+        char *s = STR(a  +   \"b\\n\");
+        int CAT(my, var) = XCAT(1, 2) + CAT(, 3) + F(7);
+        LOOP;
+    ";
+    let expected = "
+        char *s = \"a + \\\"b\\\\n\\\"\";
+        int myvar = 12 + 3 + 7;
+        for (;;) LOOP;
+    ";
+    assert_eq!(expand(source), Lexer::new(expected).tokenize());
+}
+
+#[test]
+fn test_preprocessor_errors() {
+    let source = "#define\n#define F(a, a) a\n#define G(x) #y\n#define H(x) ## x\n#define N 1\n#define N 2\n#define P(a, b) a\nint x = P(1);\n";
+    assert_eq!(preprocess(source), Err(vec![
+        "test.c:1: Macro names must be identifiers".to_string(),
+        "test.c:2: Duplicate macro parameter 'a'".to_string(),
+        "test.c:3: '#' is not followed by a macro parameter".to_string(),
+        "test.c:4: '##' cannot appear at either end of a macro expansion".to_string(),
+        "test.c:6: Macro 'N' redefined".to_string(),
+        "test.c:8: Macro 'P' expects 2 argument(s), but 1 were given".to_string(),
+    ]));
+}
+
+#[test]
+fn test_preprocessor_includes() {
+    let dir = scratch_dir("includes", &[
+        ("src/main.c", "#include \"lib/a.h\"\n#include <sys.h>\nint x = A + SYS;\n"),
+        ("src/lib/a.h", "#include \"b.h\"\n#define A (B + 1)\n"),
+        ("src/lib/b.h", "\n#define B 40\n"),
+        ("system/sys.h", "#define SYS 1\n"),
+    ]);
+    let main = dir.join("src/main.c");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.include_dir(dir.join("system").to_str().unwrap());
+    let output = preprocessor.preprocess(&fs::read_to_string(&main).unwrap(), main.to_str().unwrap()).unwrap();
+
+    // "..." is relative to the including file, <...> comes from the -I directories
+    let mut lexer = Lexer::new(&output);
+    assert_eq!(lexer.tokenize(), Lexer::new("int x = (40 + 1) + 1;").tokenize());
+
+    // #line markers bring the lexer back to the end of main.c
+    assert_eq!(lexer.line, 4);
+    assert_eq!(lexer.file, Some(main.display().to_string()));
+}
+
+#[test]
+fn test_preprocessor_include_errors() {
+    let dir = scratch_dir("include_errors", &[
+        ("main.c", "#include <a.h>\n#include \"missing.h\"\n#include a.h\n#include \"a.h\"\n"),
+        ("a.h", "#include \"b.h\"\n"),
+        ("b.h", "\n#include \"a.h\"\n"),
+    ]);
+    let main = dir.join("main.c");
+    let (a, b) = (dir.join("a.h").display().to_string(), dir.join("b.h").display().to_string());
+    let result = Preprocessor::new().preprocess(&fs::read_to_string(&main).unwrap(), main.to_str().unwrap());
+    assert_eq!(result, Err(vec![
+        format!("{}:1: Include file 'a.h' not found", main.display()),
+        format!("{}:2: Include file 'missing.h' not found", main.display()),
+        format!("{}:3: #include expects \"FILENAME\" or <FILENAME>", main.display()),
+        format!("{}:2: Include cycle: {} -> {} -> {}", b, a, b, a),
+    ]));
+}
+
+#[test]
+fn test_preprocessor_conditionals() {
+    let source = "
+        #if defined(LEVEL) && LEVEL > 1
+        int a = LEVEL;
+        #elif defined LEVEL
+        int a = 1;
+        #else
+        int a = 0;
+        #endif
+        #ifdef DEBUG
+        #if 1 / 0
+        #endif
+        #else
+        int b;
+        #endif
+        #if 0 && 1 / 0 || (2 ? 3 : 1 / 0) == 3 && 'a' == 97 && 0x10 == 020 && ~0 == -1 && UNDEFINED == 0
+        int c;
+        #endif
+    ";
+    let run = |options: &[&str]| {
+        let mut preprocessor = Preprocessor::new();
+        for option in options {
+            match option.strip_prefix("-U") {
+                Some(name) => preprocessor.undefine(name),
+                None => preprocessor.define(&option[2..]),
+            }
+        }
+        Lexer::new(&preprocessor.preprocess(source, "test.c").unwrap()).tokenize()
+    };
+    assert_eq!(run(&[]), Lexer::new("int a = 0; int b; int c;").tokenize());
+    assert_eq!(run(&["-DLEVEL=3"]), Lexer::new("int a = 3; int b; int c;").tokenize());
+    assert_eq!(run(&["-DLEVEL", "-DDEBUG", "-UDEBUG"]), Lexer::new("int a = 1; int b; int c;").tokenize());
+}
+
+#[test]
+fn test_preprocessor_conditional_errors() {
+    let source = "#if\n#endif\n#if 1 / 0\n#else\n#elif 1\n#endif\n#endif\n#if defined(\n#endif\n#ifdef X\n";
+    assert_eq!(preprocess(source), Err(vec![
+        "test.c:1: #if with no expression".to_string(),
+        "test.c:3: Division by zero in #if expression".to_string(),
+        "test.c:5: #elif after #else".to_string(),
+        "test.c:7: #endif without #if".to_string(),
+        "test.c:8: Operator 'defined' requires an identifier".to_string(),
+        "test.c:10: Unterminated conditional directive".to_string(),
+    ]));
+}
+
+#[test]
+fn test_preprocessor_builtin_headers_declare_the_vm_builtins() {
+    let source = "#include <stdio.h>\n#include <stdlib.h>\n#include <string.h>\n#include <memory.h>\n#include \"fcntl.h\"\n#include <stdio.h>\n";
+    let ast = Parser::new(expand(source)).parse_program();
+
+    let mut declared: Vec<String> = ast.iter().filter_map(|node| match node {
+        ASTNode::FuncDecl { name, .. } => Some(name.clone()),
+        _ => None,
+    }).collect();
+    let mut expected: Vec<String> = builtins().iter().map(|builtin| builtin.name.to_string()).collect();
+    declared.sort();
+    expected.sort();
+    assert_eq!(declared, expected);
+
+    // The prototypes agree with the builtins they declare
+    assert_eq!(Analyzer::new().analyze(&ast), Ok(()));
+    let mut checker = TypeChecker::new();
+    checker.check_program(ast);
+    assert!(checker.errors.is_empty());
+}
+
+#[test]
+fn test_preprocessor_predefined_macros() {
+    let dir = scratch_dir("predefined", &[
+        ("main.c", "#include \"a.h\"\nchar *m = __FILE__;\n#if defined(__LINE__) && __LINE__ == 3 && __C4_RUST__ == 1\nint line = __LINE__;\n#endif\n"),
+        ("a.h", "char *a = __FILE__;\n"),
+    ]);
+    let main = dir.join("main.c");
+    let output = Preprocessor::new().preprocess(&fs::read_to_string(&main).unwrap(), main.to_str().unwrap()).unwrap();
+
+    // __FILE__ names the file it appears in, __LINE__ is resolved by the lexer or by #if
+    let expected = format!(
+        "char *a = \"{}\"; char *m = \"{}\"; int line = 4;",
+        dir.join("a.h").display(),
+        main.display()
+    );
+    assert_eq!(Lexer::new(&output).tokenize(), Lexer::new(&expected).tokenize());
+
+    // "Mmm dd yyyy" and "hh:mm:ss"
+    let date = preprocess("__DATE__ __TIME__").unwrap();
+    let mut parts = date.split('"').filter(|part| !part.trim().is_empty());
+    let (day, time) = (parts.next().unwrap(), parts.next().unwrap());
+    assert_eq!((day.len(), &day[3..4], &day[6..7]), (11, " ", " "));
+    assert_eq!((time.len(), &time[2..3], &time[5..6]), (8, ":", ":"));
+}
+
+#[test]
+fn test_preprocessor_include_guard_must_enclose_the_whole_header() {
+    let dir = scratch_dir("include_guards", &[
+        ("main.c", "#include \"g.h\"\n#include \"guarded.h\"\n#include \"guarded.h\"\n#include \"else.h\"\n"),
+        ("g.h", "#ifndef A\nint froma;\n#endif\nint fromg = 5;\n#ifdef NEVER\n#endif\n"),
+        ("guarded.h", "// guarded header\n\n#ifndef GUARDED_H\n#define GUARDED_H\n#ifdef X\n#endif\nint once;\n#endif // GUARDED_H\n// end\n"),
+        ("else.h", "#ifndef A\nint e1;\n#else\nint e2;\n#endif\n"),
+    ]);
+    let main = dir.join("main.c");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.define("A=1");
+    let output = preprocessor.preprocess(&fs::read_to_string(&main).unwrap(), main.to_str().unwrap()).unwrap();
+    assert_eq!(Lexer::new(&output).tokenize(), Lexer::new("int fromg = 5; int once; int e2;").tokenize());
+}
@@ -0,0 +1,151 @@
+use c4::lexer::*;
+use c4::parser::*;
+use c4::sema::*;
+
+/// Parses a C source string and runs the semantic checks on it
+fn analyze_source(source: &str) -> Result<(), Vec<String>> {
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+    Analyzer::new().analyze(&ast)
+}
+
+#[test]
+fn test_sema_valid_program() {
+    let source = "
+        int total;
+        int add(int a, int b) { return a + b; }
+        int main() {
+            int x = add(1, 2);
+            total = helper(x);
+            printf(\"%d\\n\", total);
+            return 0;
+        }
+        int helper(int n) { return n * 2; }
+    ";
+    assert_eq!(analyze_source(source), Ok(()));
+}
+
+#[test]
+fn test_sema_undeclared_variable() {
+    let source = "
+        int main() {
+            if (1) { int y = 2; }
+            y = 3;
+            return 0;
+        }
+    ";
+    assert_eq!(
+        analyze_source(source),
+        Err(vec!["Undeclared variable 'y' (in function 'main')".to_string()])
+    );
+}
+
+#[test]
+fn test_sema_redeclaration() {
+    let source = "
+        int g;
+        int g;
+        int f(int a, int a) { return 0; }
+        int main() { int x; int x; return 0; }
+    ";
+    assert_eq!(
+        analyze_source(source),
+        Err(vec![
+            "Redeclaration of 'g'".to_string(),
+            "Redeclaration of 'a' (in function 'f')".to_string(),
+            "Redeclaration of 'x' (in function 'main')".to_string(),
+        ])
+    );
+}
+
+#[test]
+fn test_sema_bad_calls() {
+    let source = "
+        int add(int a, int b) { return a + b; }
+        int main() {
+            add(1);
+            missing(2);
+            printf();
+            return 0;
+        }
+    ";
+    assert_eq!(
+        analyze_source(source),
+        Err(vec![
+            "Function 'add' expects 2 argument(s), but 1 were given (in function 'main')".to_string(),
+            "Call to undefined function 'missing' (in function 'main')".to_string(),
+            "Function 'printf' expects at least 1 argument (in function 'main')".to_string(),
+        ])
+    );
+}
+
+#[test]
+fn test_sema_switch_labels() {
+    let source = "
+        int main() {
+            int x = 1;
+            switch (x) {
+                case 1: break;
+                case 2 - 1: break;
+                default: break;
+                default: break;
+            }
+            case 3: x = 2;
+            break;
+            return x;
+        }
+    ";
+    assert_eq!(analyze_source(source), Err(vec![
+        "Duplicate case value '1' (in function 'main')".to_string(),
+        "Multiple default labels in one switch (in function 'main')".to_string(),
+        "Case label not within a switch statement (in function 'main')".to_string(),
+        "Break statement not within loop or switch (in function 'main')".to_string(),
+    ]));
+}
+
+#[test]
+fn test_sema_goto_labels() {
+    let source = "
+        int f() { goto out; out: return 0; }
+        int main() {
+            top: goto out;
+            while (1) { top: break; }
+            return 0;
+        }
+    ";
+    assert_eq!(analyze_source(source), Err(vec![
+        "Duplicate label 'top' (in function 'main')".to_string(),
+        "Label 'out' used but not defined (in function 'main')".to_string(),
+    ]));
+}
+
+#[test]
+fn test_sema_function_prototypes() {
+    let source = "
+        int add(int a, int b);
+        int add(int, int);
+        int add(int a, int b) { return a + b; }
+        int add(int a);
+        int missing(int n);
+        void *malloc(int size);
+        int printf(char *format);
+        int main() { return add(1, 2) + missing(3); }
+    ";
+    assert_eq!(analyze_source(source), Err(vec![
+        "Conflicting types for 'add'".to_string(),
+        "Conflicting types for 'printf'".to_string(),
+        "Call to undefined function 'missing' (in function 'main')".to_string(),
+    ]));
+}
+
+#[test]
+fn test_sema_variable_in_scope_in_its_own_initializer() {
+    let source = "
+        struct node { int value; struct node *next; };
+        int main() {
+            struct node *m = malloc(sizeof(*m));
+            return 0;
+        }
+    ";
+    assert_eq!(analyze_source(source), Ok(()));
+}
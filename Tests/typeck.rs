@@ -0,0 +1,150 @@
+use c4::lexer::*;
+use c4::parser::*;
+use c4::typeck::*;
+use c4::types::*;
+
+/// Parses a C source string and type checks it
+fn check_source(source: &str) -> (Vec<ASTNode>, TypeChecker) {
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+    let mut checker = TypeChecker::new();
+    let ast = checker.check_program(ast);
+    (ast, checker)
+}
+
+#[test]
+fn test_typeck_pointer_arithmetic_is_scaled() {
+    let (ast, checker) = check_source("int *p; int *q = p + 2;");
+    assert!(checker.errors.is_empty());
+
+    let expected = ASTNode::Block(vec![ASTNode::DeclAssign {
+        typename: Type::Int.pointer_to(),
+        name: "q".into(),
+        value: Box::new(ASTNode::BinaryOp {
+            op: Token::Add,
+            left: Box::new(ASTNode::Id("p".into())),
+            right: Box::new(ASTNode::BinaryOp {
+                op: Token::Mul,
+                left: Box::new(ASTNode::Num(2)),
+                right: Box::new(ASTNode::Num(8)),
+            }),
+        }),
+    }]);
+    assert_eq!(ast[1], expected);
+}
+
+#[test]
+fn test_typeck_inserts_implicit_conversions() {
+    let (ast, checker) = check_source("char c = 65; int i = c + 1;");
+    assert!(checker.errors.is_empty());
+
+    assert_eq!(ast[0], ASTNode::Block(vec![ASTNode::DeclAssign {
+        typename: Type::Char,
+        name: "c".into(),
+        value: Box::new(ASTNode::Cast {
+            typename: Type::Char,
+            expr: Box::new(ASTNode::Num(65)),
+        }),
+    }]));
+    assert_eq!(ast[1], ASTNode::Block(vec![ASTNode::DeclAssign {
+        typename: Type::Int,
+        name: "i".into(),
+        value: Box::new(ASTNode::BinaryOp {
+            op: Token::Add,
+            left: Box::new(ASTNode::Cast {
+                typename: Type::Int,
+                expr: Box::new(ASTNode::Id("c".into())),
+            }),
+            right: Box::new(ASTNode::Num(1)),
+        }),
+    }]));
+}
+
+#[test]
+fn test_typeck_incompatible_operands() {
+    let source = "
+        int main() {
+            char *a = \"x\";
+            int i = a;
+            i = a + a;
+            i = *i;
+            return 0;
+        }
+    ";
+    let (_, checker) = check_source(source);
+    assert_eq!(checker.warnings, vec![
+        "initialization makes integer from pointer without a cast (in function 'main')".to_string(),
+    ]);
+    assert_eq!(checker.errors, vec![
+        "invalid operands to binary + ('char *' and 'char *') (in function 'main')".to_string(),
+        "bad dereference (in function 'main')".to_string(),
+    ]);
+}
+
+#[test]
+fn test_typeck_explicit_cast_silences_pointer_warning() {
+    let (_, checker) = check_source("int c; int *p = c; int *q = (int *)c;");
+    assert!(checker.errors.is_empty());
+    assert_eq!(checker.warnings.len(), 1);
+}
+
+#[test]
+fn test_typeck_struct_member_errors() {
+    let (_, checker) = check_source("struct a { int x; }; struct b; struct a s; struct b t; int i = s.y;");
+    assert_eq!(checker.errors, vec![
+        "storage size of 't' isn't known".to_string(),
+        "'struct a' has no member named 'y'".to_string(),
+    ]);
+}
+
+#[test]
+fn test_typeck_floating_conversions() {
+    let (ast, checker) = check_source("double d = 1 + 0.5; int main() { int *p = d; if (d) { return 1; } return (char *)d; }");
+    assert_eq!(ast[0], ASTNode::Block(vec![ASTNode::DeclAssign {
+        typename: Type::Double,
+        name: "d".into(),
+        value: Box::new(ASTNode::BinaryOp {
+            op: Token::Add,
+            left: Box::new(ASTNode::Cast {
+                typename: Type::Double,
+                expr: Box::new(ASTNode::Num(1)),
+            }),
+            right: Box::new(ASTNode::FNum(0.5)),
+        }),
+    }]));
+
+    // A floating condition is a comparison with zero
+    let ASTNode::FuncDef { body, .. } = &ast[1] else { panic!("expected main") };
+    let ASTNode::If { cond, .. } = &body[1] else { panic!("expected if") };
+    assert_eq!(**cond, ASTNode::BinaryOp {
+        op: Token::Ne,
+        left: Box::new(ASTNode::Id("d".into())),
+        right: Box::new(ASTNode::FNum(0.0)),
+    });
+
+    assert_eq!(checker.errors, vec![
+        "incompatible types in initialization ('int *' from 'double') (in function 'main')".to_string(),
+        "invalid cast from 'double' to 'char *' (in function 'main')".to_string(),
+    ]);
+}
+
+#[test]
+fn test_typeck_variable_in_scope_in_its_own_initializer() {
+    let (ast, checker) = check_source("struct node { int value; struct node *next; }; struct node *m = malloc(sizeof(*m));");
+    assert!(checker.errors.is_empty());
+    let ASTNode::Block(decls) = &ast[1] else { panic!("expected a declaration") };
+    let ASTNode::DeclAssign { value, .. } = &decls[0] else { panic!("expected a declaration") };
+    // sizeof(*m) is the size of the struct m points to
+    assert_eq!(**value, ASTNode::FuncCall { name: "malloc".into(), args: vec![ASTNode::Num(16)] });
+}
+
+#[test]
+fn test_typeck_initializers_must_fit_the_array() {
+    let source = "char s[3] = \"abc\"; char t[2] = \"abc\"; int a[2] = {1, 2, 3}; int b[2][2] = {{1, 2}, {{3, 4}}};";
+    let (_, checker) = check_source(source);
+    assert_eq!(checker.errors, vec![
+        "initializer-string for array of 'char' is too long".to_string(),
+        "excess elements in array initializer".to_string(),
+        "excess elements in scalar initializer".to_string(),
+    ]);
+}
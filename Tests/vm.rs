@@ -1,5 +1,6 @@
 use c4::lexer::*;
 use c4::parser::*;
+use c4::typeck::*;
 use c4::vm::*;
 use std::collections::HashMap;
 
@@ -127,3 +128,492 @@ fn test_vm_function_call() {
     // Check if the `main` function returned 42
     assert_eq!(result, 42);
 }
+
+/// Compiles and runs a C source string, returning main's return value
+fn run_source(source: &str) -> i64 {
+    let tokens = Lexer::new(source).tokenize();
+    let ast = Parser::new(tokens).parse_program();
+    let ast = TypeChecker::new().check_program(ast);
+    let (instructions, functions) = generate(ast);
+    VM::new(instructions, functions).run()
+}
+
+#[test]
+fn test_vm_local_and_global_arrays() {
+    let source = "
+        int g[4] = {1, 2, 3};
+        int main() {
+            int a[3];
+            char s[] = \"hi\";
+            a[0] = 7;
+            a[2] = g[1] + g[3];
+            s[1] = 'a';
+            return a[0] * 100 + a[2] * 10 + (s[1] - 'a') + s[2];
+        }
+    ";
+    assert_eq!(run_source(source), 720);
+}
+
+#[test]
+fn test_vm_pointers() {
+    let source = "
+        void set(int *p, int v) { *p = v; }
+        int main() {
+            int x = 1;
+            int m[2][3] = {{1, 2, 3}, {4, 5, 6}};
+            char *s = \"abc\";
+            int *p = &x;
+            set(&x, 40);
+            *p = *p + 2;
+            s = s + 1;
+            return x * 1000 + m[1][2] * 100 + *s;
+        }
+    ";
+    assert_eq!(run_source(source), 42000 + 600 + 98);
+}
+
+#[test]
+fn test_vm_conversions_and_pointer_arithmetic() {
+    let source = "
+        int main() {
+            int a[4] = {10, 20, 30, 40};
+            int *p = a;
+            int *q = p + 3;
+            char c = 300;
+            return (q - p) * 1000 + *(p + 2) + c;
+        }
+    ";
+    assert_eq!(run_source(source), 3000 + 30 + 44);
+}
+
+#[test]
+fn test_vm_sizeof_and_malloc() {
+    let source = "
+        int main() {
+            int a[10];
+            int *p = malloc(3 * sizeof(int));
+            p[2] = sizeof a + sizeof(char);
+            return p[2] + sizeof *p + sizeof(int *);
+        }
+    ";
+    assert_eq!(run_source(source), 80 + 1 + 8 + 8);
+}
+
+#[test]
+fn test_vm_casts() {
+    let source = "
+        int main() {
+            int c = 321;
+            char *p = (char *)malloc(4);
+            p[0] = (char)(c + 1);
+            return (char)c * 1000 + (int)p[0];
+        }
+    ";
+    assert_eq!(run_source(source), 65066);
+}
+
+#[test]
+fn test_vm_named_enum() {
+    let source = "
+        enum color { RED, GREEN = 4, BLUE };
+        enum color next(enum color c) {
+            if (c == GREEN) { return BLUE; }
+            return GREEN;
+        }
+        int main() {
+            enum color c = RED;
+            return c + next(c) * 10 + next(GREEN) * 100;
+        }
+    ";
+    assert_eq!(run_source(source), 540);
+}
+
+#[test]
+fn test_vm_structs_and_linked_list() {
+    let source = "
+        struct node { int value; struct node *next; };
+        struct point { int x; int y; };
+        struct line { struct point from; struct point to; };
+
+        struct node *push(struct node *head, int value) {
+            struct node *n = malloc(sizeof(struct node));
+            n->value = value;
+            n->next = head;
+            return n;
+        }
+
+        int length(struct line *l) {
+            return l->to.x - l->from.x + l->to.y - l->from.y;
+        }
+
+        int main() {
+            struct node *list = 0;
+            list = push(list, 4);
+            list = push(list, 5);
+            int total = 0;
+            while (list) {
+                total = total * 10 + list->value;
+                list = list->next;
+            }
+            struct line l = {{1, 2}, {4, 8}};
+            return total * 100 + length(&l);
+        }
+    ";
+    assert_eq!(run_source(source), 5409);
+}
+
+#[test]
+fn test_vm_switch_fall_through_and_break() {
+    let source = "
+        int classify(int c) {
+            int r = 0;
+            switch (c) {
+                case 'a':
+                case 'e': r = 1; break;
+                case 'z': r = 10;
+                default: r = r + 100;
+            }
+            return r;
+        }
+        int main() {
+            int n = 0;
+            while (1) {
+                n = n + 1;
+                if (n == 4) { break; }
+            }
+            return classify('a') + classify('e') * 2 + classify('z') * 1000 + classify('q') * 100000 + n;
+        }
+    ";
+    assert_eq!(run_source(source), 1 + 2 + 110000 + 10000000 + 4);
+}
+
+#[test]
+fn test_vm_typedefs() {
+    let source = "
+        typedef int number;
+        typedef struct pair { number first; number second; } Pair, *PairPtr;
+        typedef number row[4];
+
+        number sum(PairPtr p) {
+            return p->first + p->second;
+        }
+
+        int main() {
+            Pair p = {3, 4};
+            row r = {1, 2, 3, 4};
+            PairPtr q = (PairPtr)malloc(sizeof(Pair));
+            q->first = r[3];
+            q->second = sizeof(row);
+            return sum(&p) * 100 + sum(q);
+        }
+    ";
+    assert_eq!(run_source(source), 736);
+}
+
+#[test]
+fn test_vm_goto_loop() {
+    let source = "
+        int main() {
+            int i = 0;
+            int total = 0;
+            goto check;
+        body:
+            total = total + i;
+            i = i + 1;
+        check:
+            if (i < 5) { goto body; }
+            return total;
+        }
+    ";
+    assert_eq!(run_source(source), 10);
+}
+
+#[test]
+fn test_vm_comma_operator_and_for_loops() {
+    let source = "
+        int main() {
+            int a[4] = {1, 2, 3, 4};
+            int i, j, t;
+            for (i = 0, j = 3; i < j; i++, j--) {
+                t = a[i];
+                a[i] = a[j];
+                a[j] = t;
+            }
+            int total = 0;
+            for (int k = 0; k < 4; k++) {
+                total = total * 10 + a[k];
+            }
+            int n = 0;
+            for (;;) {
+                if (n++ == 2) { break; }
+            }
+            return total * 10 + (n, 7);
+        }
+    ";
+    assert_eq!(run_source(source), 43217);
+}
+
+#[test]
+fn test_vm_unary_minus_plus_and_complement() {
+    let source = "
+        int offset = -100;
+        int main() {
+            int a[3] = {-1, 2, -3};
+            int x = 6;
+            return offset + a[0] * a[2] - -x + ~x * 10 + +x;
+        }
+    ";
+    assert_eq!(run_source(source), -100 + 3 + 6 - 70 + 6);
+}
+
+#[test]
+fn test_vm_conditional_expressions() {
+    let source = "
+        int sign(int x) {
+            return x > 0 ? 1 : x < 0 ? -1 : 0;
+        }
+        int main() {
+            int a = 4;
+            char *s = a > 3 ? \"yes\" : \"no\";
+            int m = (a > 2 ? a : 2) * 10;
+            return sign(7) * 1000 + sign(-7) * 100 + sign(0) + s[0] + m;
+        }
+    ";
+    assert_eq!(run_source(source), 1000 - 100 + 'y' as i64 + 40);
+}
+
+#[test]
+fn test_vm_assignment_is_an_expression() {
+    let source = "
+        int calls;
+        int next(char *s) {
+            calls = calls + 1;
+            return s[calls - 1];
+        }
+        int main() {
+            int a, b, v;
+            int *p = &v;
+            a = b = *p = 3;
+            int c, n = 0;
+            while ((c = next(\"xyz\")) != 0) {
+                n = n + 1;
+            }
+            return (a = a + v) * 1000 + b * 100 + n * 10 + (calls == 4);
+        }
+    ";
+    assert_eq!(run_source(source), 6331);
+}
+
+#[test]
+fn test_vm_functions_assign_globals_not_caller_locals() {
+    let source = "
+        int x = 1;
+        int set() { x = 5; return x; }
+        int main() {
+            int r = set();
+            int x = 2;
+            set();
+            return r * 10 + x;
+        }
+    ";
+    assert_eq!(run_source(source), 52);
+}
+
+#[test]
+fn test_vm_escapes_are_stored_as_single_bytes() {
+    let source = "
+        int main() {
+            char s[] = \"a\\xff\\101\\0b\";
+            return sizeof(s) * 1000 + (s[1] == '\\xff') * 100 + (s[1] == -1) * 10 + s[2] - '\\101' + s[3];
+        }
+    ";
+    assert_eq!(run_source(source), 6110);
+}
+
+#[test]
+fn test_vm_adjacent_string_literals() {
+    let source = "
+        char s[] = \"ab\" \"c\"
+            \"d\";
+        int main() {
+            char *t = \"x\" \"\" \"yz\";
+            return sizeof(s) * 100 + t[2];
+        }
+    ";
+    assert_eq!(run_source(source), 500 + 'z' as i64);
+}
+
+#[test]
+fn test_vm_uses_64_bit_words() {
+    let source = "
+        int big = 0x100000000L;
+        int main() {
+            int *p = malloc(2 * sizeof(int));
+            p[0] = big * 3;
+            p[1] = -1;
+            char *bytes = (char *)p;
+            return (p[0] == 12884901888) * 1000 + p[0] / big * 100 + sizeof(p) * 10 + (bytes[4] == 3);
+        }
+    ";
+    assert_eq!(run_source(source), 1381);
+}
+
+#[test]
+fn test_vm_floating_point() {
+    let source = "
+        float scale = 2.5f;
+        double half(double x) { return x / 2; }
+        int main() {
+            float f = 0.1;
+            float a[3] = {1, 2.5, -0.25};
+            double d = 7 / 2;
+            double e = half(7);
+            int checks = (f != 0.1) + (f == (float)0.1) + (a[1] + a[2] == 2.25) + (-e < -3.4) + !0.0;
+            e++;
+            return checks * 10000 + (int)(e * 100) + (int)(d * scale) * 1000 + (char)300.7;
+        }
+    ";
+    // checks = 5, e = 4.5, d * scale = 7.5 and (char)300 = 44
+    assert_eq!(run_source(source), 50000 + 450 + 7000 + 44);
+}
+
+#[test]
+fn test_vm_printf_formats_floats() {
+    let bits = |value: f64| value.to_bits() as i64;
+    assert_eq!(format_printf("%f %g %g %e", &[bits(1.5), bits(0.001), bits(1e-5), bits(123456789.0)], &[]),
+        "1.500000 0.001 1e-05 1.234568e+08");
+    assert_eq!(format_printf("%g %g %.3f %G", &[bits(100000.0), bits(1000000.0), bits(-2.0005), bits(f64::INFINITY)], &[]),
+        "100000 1e+06 -2.001 INF");
+    assert_eq!(format_printf("[%5.1f|%-6g|%+d|%05d|%.3d|%%|%", &[bits(4.25), bits(0.5), 3, -42, 7], &[]),
+        "[  4.2|0.5   |+3|-0042|007|%|%");
+}
+
+#[test]
+fn test_vm_printf_formats_chars_strings_and_unsigned() {
+    let memory = b"\0hello\0";
+    assert_eq!(format_printf("%c %d", &['a' as i64, 5], memory), "a 5");
+    assert_eq!(format_printf("[%s|%-7s|%.3s|%s]", &[1, 1, 1, 0], memory), "[hello|hello  |hel|(null)]");
+    assert_eq!(format_printf("%x %X %o %u %+u %4c|", &[255, 255, 8, -1, 7, 'z' as i64], memory),
+        "ff FF 10 18446744073709551615 7    z|");
+    assert_eq!(format_printf("%p %d", &[1, 2], memory), "%p 2");
+}
+
+#[test]
+fn test_vm_block_arrays_in_loops_reuse_their_memory() {
+    let source = "
+        int main() {
+            int i = 0;
+            int total = 0;
+            while (i < 5000) {
+                int a[100];
+                int x = i;
+                int *p = &x;
+                a[99] = *p;
+                total = total + a[99] - i + a[0];
+                i++;
+            }
+            return total + i;
+        }
+    ";
+    assert_eq!(run_source(source), 5000);
+}
+
+#[test]
+fn test_vm_array_sizes_are_constant_expressions() {
+    let source = "
+        enum { MAX = 4 };
+        int g[MAX * 2 - 1];
+        int main() {
+            int a[MAX];
+            char b[2*3][MAX];
+            a[MAX - 1] = 5;
+            return sizeof(g) / sizeof(int) * 1000 + sizeof(a) / sizeof(int) * 100 + sizeof(b) + a[3];
+        }
+    ";
+    assert_eq!(run_source(source), 7000 + 400 + 24 + 5);
+}
+
+#[test]
+fn test_vm_block_locals_shadow_outer_variables() {
+    let source = "
+        int x = 5;
+        int global() { return x; }
+        int main() {
+            int x = 1;
+            int inner = 0;
+            { int x = 2; inner = x; x = 3; }
+            for (int x = 7; x < 8; x++) { inner = inner * 10 + x; }
+            if (x) { int x = 4; int *p = &x; inner = inner * 10 + *p; }
+            return inner * 100 + x * 10 + global();
+        }
+    ";
+    assert_eq!(run_source(source), 27400 + 10 + 5);
+}
+
+#[test]
+fn test_vm_braceless_for_bodies() {
+    let source = "
+        int main() {
+            int i;
+            int j;
+            int sum = 0;
+            for (i = 0; i < 5; i++) sum = sum + i;
+            for (i = 0; i < 3; i++) for (j = 0; j < 2; j++) sum++;
+            for (i = 0; i < 4; i++);
+            return sum * 10 + i;
+        }
+    ";
+    assert_eq!(run_source(source), 164);
+}
+
+#[test]
+fn test_vm_arithmetic_wraps_like_c4() {
+    let source = "
+        int main() {
+            char *s = \"a string long enough to overflow the hash\";
+            int h = 0;
+            int min = -9223372036854775807 - 1;
+            while (*s) {
+                h = h * 147 + *s;
+                s++;
+            }
+            return (h == 6371142125183695922) * 100 + (min / -1 == min) * 10 + (min % -1 == 0);
+        }
+    ";
+    assert_eq!(run_source(source), 111);
+}
+
+#[test]
+fn test_vm_division_by_zero_is_a_runtime_error() {
+    let tokens = Lexer::new("int zero; int main() { printf(\"%d\", 1 % zero); return 1 / zero; }").tokenize();
+    let ast = TypeChecker::new().check_program(Parser::new(tokens).parse_program());
+    let (instructions, functions) = generate(ast);
+    let mut vm = VM::new(instructions, functions);
+    vm.run();
+    assert_eq!(vm.error, Some("Division by zero".to_string()));
+}
+
+#[test]
+fn test_vm_memory_and_file_builtins() {
+    let path = std::env::temp_dir().join("c4_rust_vm_file_builtins.txt");
+    std::fs::write(&path, "hello").unwrap();
+    let source = format!("
+        int main() {{
+            char a[8];
+            char b[8];
+            memset(a, 'x', 8);
+            memcpy(b, a, 8);
+            b[7] = 'y';
+            int fd = open(\"{path}\", 0);
+            int n = read(fd, a, 8);
+            int closed = close(fd);
+            int again = close(fd);
+            int missing = open(\"{path}.missing\", 0);
+            return (memcmp(a, b, 7) < 0) * 100000 + (memcmp(b + 5, a + 5, 3) > 0) * 10000 + n * 1000
+                + (a[4] == 'o') * 100 + (closed == 0) * 10 + (again == -1) + (missing == -1);
+        }}
+    ", path = path.display());
+    assert_eq!(run_source(&source), 115112);
+
+    assert_eq!(run_source("int main() { exit(3); return 5; }"), 3);
+}
@@ -1,13 +1,23 @@
 // Virtual Machine file
 use crate::parser::ASTNode; // used to convert ast to instructions
-use crate::lexer::Token;    // our token enum
-use std::collections::HashMap;
+use crate::lexer::{literal_bytes, Token}; // our token enum
+use crate::types::{Member, Type}; // declared types, for sizes, offsets and load/store width
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// Size in bytes of an `int` (and of a pointer) in VM memory, 64-bit as in c4
+pub const WORD_SIZE: usize = 8;
+
+/// Size in bytes of the VM's addressable memory
+pub const MEMORY_SIZE: usize = 1 << 20;
 
 /// Represents instructions that can be executed by the VM
 #[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]  // Suppress warnings for unused variants
+#[allow(clippy::upper_case_acronyms)]  // Mnemonics follow the original c4 opcodes
 pub enum Instruction {
-    IMM(i32),
+    IMM(i64),
     LC, LI, SC, SI,
     PUSH,
     JMP(usize), JZ(usize), JNZ(usize),
@@ -17,13 +27,47 @@ pub enum Instruction {
     ADJ(usize),
     LEV, LEA(usize),
     LOAD(String), STORE(String),
+    DEFINE(String), // like STORE, but always creates the variable in the current scope
     PRINTF(String, Vec<String>),
     OR, XOR, AND, EQ, NE, LT, LE, GT, GE, SHL, SHR,
     ADD, SUB, MUL, DIV, MOD,
+    // Floating values are held in words as the bits of a double; LF/SF access a 4-byte float
+    FIMM(f64),
+    LF, SF,
+    FADD, FSUB, FMUL, FDIV, FEQ, FNE, FLT, FGT,
+    ITF, FTI, DTF, // int to double, double to int, double rounded to float precision
     EXIT,
     LoadString(String),
     DEREF,
     ADDR(String),
+    ALLOC(usize),
+    MALC, FREE,
+    MSET, MCMP, MCPY, OPEN, READ, CLOS,
+}
+
+/// A library function the VM implements itself instead of running compiled code
+pub struct Builtin {
+    pub name: &'static str,
+    pub return_type: Type,
+    pub params: Vec<Type>,
+    pub variadic: bool,
+}
+
+/// Returns every builtin function, with the signature used to check calls to it
+pub fn builtins() -> Vec<Builtin> {
+    let void_ptr = Type::Void.pointer_to();
+    vec![
+        Builtin { name: "printf", return_type: Type::Int, params: vec![Type::Char.pointer_to()], variadic: true },
+        Builtin { name: "malloc", return_type: void_ptr.clone(), params: vec![Type::Int], variadic: false },
+        Builtin { name: "free", return_type: Type::Void, params: vec![void_ptr.clone()], variadic: false },
+        Builtin { name: "exit", return_type: Type::Void, params: vec![Type::Int], variadic: false },
+        Builtin { name: "memset", return_type: void_ptr.clone(), params: vec![void_ptr.clone(), Type::Int, Type::Int], variadic: false },
+        Builtin { name: "memcmp", return_type: Type::Int, params: vec![void_ptr.clone(), void_ptr.clone(), Type::Int], variadic: false },
+        Builtin { name: "memcpy", return_type: void_ptr.clone(), params: vec![void_ptr.clone(), void_ptr, Type::Int], variadic: false },
+        Builtin { name: "open", return_type: Type::Int, params: vec![Type::Char.pointer_to(), Type::Int], variadic: false },
+        Builtin { name: "read", return_type: Type::Int, params: vec![Type::Int, Type::Char.pointer_to(), Type::Int], variadic: false },
+        Builtin { name: "close", return_type: Type::Int, params: vec![Type::Int], variadic: false },
+    ]
 }
 
 /// Represents a function definition with parameters and entry point
@@ -37,15 +81,23 @@ pub struct Function {
 /// Virtual machine that executes compiled instructions
 pub struct VM {
     pub text: Vec<Instruction>,
-    pub stack: Vec<i32>,
+    pub stack: Vec<i64>,
     pub pc: usize,
     pub bp: usize,
     pub sp: usize,
-    pub ax: i32,
-    pub variables: HashMap<String, i32>,
+    pub ax: i64,
+    pub variables: HashMap<String, i64>,
     pub functions: HashMap<String, Function>,
     pub call_stack: Vec<usize>,
-    pub variable_stack: Vec<HashMap<String, i32>>,
+    pub variable_stack: Vec<HashMap<String, i64>>,
+    pub memory: Vec<u8>,
+    pub mem_top: usize,  // next free byte for arrays, released when a function returns
+    pub heap_top: usize, // grows down from the end of memory, never released
+    pub frame_tops: Vec<usize>,
+    pub frame_allocs: Vec<HashMap<usize, usize>>, // address reserved by each ALLOC in each active call
+    pub strings: HashMap<String, usize>,
+    pub error: Option<String>, // a runtime error that stopped the program, like division by zero
+    pub files: HashMap<i64, File>, // files opened by the program, by descriptor
 }
 
 impl VM {
@@ -62,12 +114,21 @@ impl VM {
             functions,
             call_stack: Vec::new(),
             variable_stack: vec![HashMap::new()],
+            memory: vec![0; MEMORY_SIZE],
+            mem_top: WORD_SIZE, // keep address 0 free so it can act as NULL
+            heap_top: MEMORY_SIZE,
+            frame_tops: Vec::new(),
+            frame_allocs: vec![HashMap::new()],
+            strings: HashMap::new(),
+            error: None,
+            files: HashMap::new(),
         }
     }
 
     /// Runs the program from start to finish and returns the final result
-    pub fn run(&mut self) -> i32 {
-        while self.pc < self.text.len() {
+    /// A runtime error stops the program early and is left in `error`
+    pub fn run(&mut self) -> i64 {
+        while self.pc < self.text.len() && self.error.is_none() {
             self.execute_instruction();
         }
         self.ax
@@ -86,7 +147,11 @@ impl VM {
             Instruction::LT => self.exec_lt(),
             Instruction::EQ => self.exec_eq(),
             Instruction::NE => self.exec_ne(),
+            Instruction::XOR => self.exec_xor(),
+            Instruction::SHL => self.exec_shl(),
+            Instruction::SHR => self.exec_shr(),
             Instruction::JZ(addr) => self.exec_jz(addr),
+            Instruction::JNZ(addr) => self.exec_jnz(addr),
             Instruction::JMP(addr) => self.exec_jmp(addr),
             Instruction::ENT(size) => self.exec_ent(size),
             Instruction::ADJ(size) => self.exec_adj(size),
@@ -95,12 +160,34 @@ impl VM {
             Instruction::CALL(name) => self.exec_call(&name),
             Instruction::RETURN => self.exec_return(),
             Instruction::STORE(name) => self.exec_store(&name),
+            Instruction::DEFINE(name) => self.exec_define(&name),
             Instruction::LOAD(name) => self.exec_load(name),
             Instruction::PRINTF(fmt, args) => self.exec_printf(&fmt, &args),
             Instruction::EXIT => self.pc = self.text.len(),
             Instruction::LoadString(string) => self.exec_load_string(string),  // Fixed: lowercase variable name
             Instruction::DEREF => self.exec_deref(),
             Instruction::ADDR(name) => self.exec_addr(&name),
+            Instruction::LI => self.exec_li(),
+            Instruction::LC => self.exec_lc(),
+            Instruction::SI => self.exec_si(),
+            Instruction::SC => self.exec_sc(),
+            Instruction::ALLOC(size) => self.exec_alloc(size),
+            Instruction::MALC => self.exec_malc(),
+            Instruction::FREE => self.exec_free(),
+            Instruction::MSET => self.exec_mset(),
+            Instruction::MCMP => self.exec_mcmp(),
+            Instruction::MCPY => self.exec_mcpy(),
+            Instruction::OPEN => self.exec_open(),
+            Instruction::READ => self.exec_read(),
+            Instruction::CLOS => self.exec_clos(),
+            Instruction::FIMM(val) => self.exec_imm(val.to_bits() as i64),
+            Instruction::LF => self.exec_lf(),
+            Instruction::SF => self.exec_sf(),
+            op @ (Instruction::FADD | Instruction::FSUB | Instruction::FMUL | Instruction::FDIV
+            | Instruction::FEQ | Instruction::FNE | Instruction::FLT | Instruction::FGT) => self.exec_float(op),
+            Instruction::ITF => self.ax = (self.ax as f64).to_bits() as i64,
+            Instruction::FTI => self.ax = f64::from_bits(self.ax as u64) as i64,
+            Instruction::DTF => self.ax = (f64::from_bits(self.ax as u64) as f32 as f64).to_bits() as i64,
             _ => panic!("Unsupported instruction: {:?}", self.text[self.pc - 1]),
         }
     }
@@ -111,7 +198,7 @@ impl VM {
         op
     }
 
-    fn exec_imm(&mut self, val: i32) {
+    fn exec_imm(&mut self, val: i64) {
         self.ax = val;
     }
 
@@ -125,7 +212,7 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = left + right;
+        self.ax = left.wrapping_add(right);
     }
 
     fn exec_sub(&mut self) {
@@ -133,7 +220,7 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = left - right;
+        self.ax = left.wrapping_sub(right);
     }
 
     fn exec_mul(&mut self) {
@@ -141,7 +228,7 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = left * right;
+        self.ax = left.wrapping_mul(right);
     }
 
     fn exec_div(&mut self) {
@@ -149,7 +236,11 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = left / right;
+        if right == 0 {
+            self.error = Some("Division by zero".to_string());
+            return;
+        }
+        self.ax = left.wrapping_div(right); // i64::MIN / -1 wraps, as in c4
     }
 
     fn exec_mod(&mut self) {
@@ -157,7 +248,11 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = left % right;
+        if right == 0 {
+            self.error = Some("Division by zero".to_string());
+            return;
+        }
+        self.ax = left.wrapping_rem(right);
     }
 
     fn exec_gt(&mut self) {
@@ -165,7 +260,7 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = (left > right) as i32;
+        self.ax = (left > right) as i64;
     }
 
     fn exec_lt(&mut self) {
@@ -173,7 +268,7 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = (left < right) as i32;
+        self.ax = (left < right) as i64;
     }
 
     fn exec_eq(&mut self) {
@@ -181,7 +276,7 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = (left == right) as i32;
+        self.ax = (left == right) as i64;
     }
 
     fn exec_ne(&mut self) {
@@ -189,7 +284,49 @@ impl VM {
         let right = self.stack[self.sp];
         self.sp -= 1;
         let left = self.stack[self.sp];
-        self.ax = (left != right) as i32;
+        self.ax = (left != right) as i64;
+    }
+
+    fn exec_xor(&mut self) {
+        self.sp -= 1;
+        let right = self.stack[self.sp];
+        self.sp -= 1;
+        let left = self.stack[self.sp];
+        self.ax = left ^ right;
+    }
+
+    fn exec_shl(&mut self) {
+        self.sp -= 1;
+        let right = self.stack[self.sp];
+        self.sp -= 1;
+        let left = self.stack[self.sp];
+        self.ax = left.wrapping_shl(right as u32);
+    }
+
+    fn exec_shr(&mut self) {
+        self.sp -= 1;
+        let right = self.stack[self.sp];
+        self.sp -= 1;
+        let left = self.stack[self.sp];
+        self.ax = left.wrapping_shr(right as u32); // arithmetic shift, keeps the sign
+    }
+
+    fn exec_float(&mut self, op: Instruction) {
+        self.sp -= 1;
+        let right = f64::from_bits(self.stack[self.sp] as u64);
+        self.sp -= 1;
+        let left = f64::from_bits(self.stack[self.sp] as u64);
+        self.ax = match op {
+            Instruction::FADD => (left + right).to_bits() as i64,
+            Instruction::FSUB => (left - right).to_bits() as i64,
+            Instruction::FMUL => (left * right).to_bits() as i64,
+            Instruction::FDIV => (left / right).to_bits() as i64,
+            Instruction::FEQ => (left == right) as i64,
+            Instruction::FNE => (left != right) as i64,
+            Instruction::FLT => (left < right) as i64,
+            Instruction::FGT => (left > right) as i64,
+            other => panic!("Not a floating point instruction: {:?}", other),
+        };
     }
 
     fn exec_jz(&mut self, addr: usize) {
@@ -198,13 +335,19 @@ impl VM {
         }
     }
 
+    fn exec_jnz(&mut self, addr: usize) {
+        if self.ax != 0 {
+            self.pc = addr;
+        }
+    }
+
     fn exec_jmp(&mut self, addr: usize) {
         self.pc = addr;
     }
 
     fn exec_ent(&mut self, size: usize) {
         // Save old base pointer
-        self.stack[self.sp] = self.bp as i32;
+        self.stack[self.sp] = self.bp as i64;
         self.sp += 1;
         
         // Set new base pointer to current stack pointer
@@ -234,12 +377,14 @@ impl VM {
 
     fn exec_lea(&mut self, offset: usize) {
         // Load effective address (local variable relative to bp)
-        self.ax = (self.bp + offset) as i32;
+        self.ax = (self.bp + offset) as i64;
     }
 
     fn exec_call(&mut self, name: &str) {
         if let Some(func) = self.functions.get(name) {
             self.call_stack.push(self.pc); // Save the return address
+            self.frame_tops.push(self.mem_top); // Arrays allocated by the callee are freed on return
+            self.frame_allocs.push(HashMap::new());
             self.pc = func.start_addr; // Jump to the function
     
             // Handle function parameters
@@ -247,9 +392,8 @@ impl VM {
             let mut param_values = Vec::new();
             for _ in 0..param_count {
                 self.sp -= 1;
-                param_values.push(self.stack[self.sp]);
+                param_values.push(self.stack[self.sp]); // Arguments are pushed last-to-first
             }
-            param_values.reverse(); // Reverse to maintain the correct order
     
             // Create a new scope for the function's variables
             let mut local_vars = HashMap::new();
@@ -265,12 +409,21 @@ impl VM {
 
     fn exec_return(&mut self) {
         self.variable_stack.pop();
+        if let Some(top) = self.frame_tops.pop() {
+            self.mem_top = top;
+            self.frame_allocs.pop();
+        }
 
         if let Some(return_addr) = self.call_stack.pop() {
             if self.sp > 0 {
                 self.sp -= 1;
                 self.ax = self.stack[self.sp];
             }
+            // Drop the frame and the base pointer saved by ENT
+            if self.bp > 0 {
+                self.sp = self.bp - 1;
+                self.bp = self.stack[self.sp] as usize;
+            }
             self.pc = return_addr; // Restore the program counter
         } else {
             // Terminate if the call stack is empty (main is returning)
@@ -278,47 +431,241 @@ impl VM {
         }
     }        
 
-    fn exec_store(&mut self, name: &str) {
+    fn exec_define(&mut self, name: &str) {
         if let Some(scope) = self.variable_stack.last_mut() {
             scope.insert(name.to_string(), self.ax);
         } else {
             panic!("No variable scope found");
         }
     }
+
+    fn exec_store(&mut self, name: &str) {
+        // Assign to the function's own variable, else to a global
+        let scope = match self.variable_stack.last() {
+            Some(scope) if scope.contains_key(name) => self.variable_stack.len() - 1,
+            _ if self.variable_stack[0].contains_key(name) => 0,
+            _ => self.variable_stack.len() - 1,
+        };
+        self.variable_stack[scope].insert(name.to_string(), self.ax);
+    }
     
     fn exec_load(&mut self, name: String) {
-        for scope in self.variable_stack.iter().rev() {
-            if let Some(value) = scope.get(&name) {
-                self.ax = *value;
-                return;
-            }
+        // Only the current function's variables and the globals are visible
+        let local = self.variable_stack.last().and_then(|scope| scope.get(&name));
+        match local.or_else(|| self.variable_stack[0].get(&name)) {
+            Some(value) => self.ax = *value,
+            None => panic!("Undefined variable: {}", name),
         }
-        panic!("Undefined variable: {}", name);
     }
     
     /// Executes a printf instruction with format string and arguments
-    pub fn exec_printf(&mut self, fmt: &String, args: &Vec<String>) {
-        let mut output = fmt.clone();
-
+    pub fn exec_printf(&mut self, fmt: &str, args: &[String]) {
+        let mut values = Vec::new();
         for _ in args {
             if self.sp == 0 {
                 panic!("Not enough values on the stack for printf");
             }
             self.sp -= 1;
-            let val = self.stack[self.sp];
-            output = output.replacen("%d", &val.to_string(), 1);
-        }
-        if output.contains("%d") {
-            panic!("Mismatch between format string and arguments");
+            values.push(self.stack[self.sp]); // Arguments are pushed last-to-first
         }
-        println!("{}", output);
+        let output = format_printf(fmt, &values, &self.memory);
+        let mut bytes = literal_bytes(&output);
+        bytes.push(b'\n');
+        std::io::stdout().write_all(&bytes).expect("Could not write to stdout");
     }    
 
     fn exec_load_string(&mut self, string: String) {
-        // Simulate storing the string in memory and returning its address
-        let address = self.stack.len(); // Use the stack length as a fake address
-        self.variables.insert(string.clone(), address as i32);
-        self.ax = address as i32;
+        // Each distinct literal is copied into the heap once, NUL terminated
+        if let Some(address) = self.strings.get(&string) {
+            self.ax = *address as i64;
+            return;
+        }
+        let bytes = literal_bytes(&string);
+        let address = self.heap_alloc(bytes.len() + 1);
+        self.memory[address..address + bytes.len()].copy_from_slice(&bytes);
+        self.strings.insert(string, address);
+        self.ax = address as i64;
+    }
+
+    /// Reserves `size` bytes at the top of memory that live for the whole run
+    fn heap_alloc(&mut self, size: usize) -> usize {
+        let size = size.div_ceil(WORD_SIZE) * WORD_SIZE;
+        if self.heap_top < self.mem_top + size {
+            panic!("Out of memory");
+        }
+        self.heap_top -= size;
+        self.heap_top
+    }
+
+    fn exec_malc(&mut self) {
+        // malloc(size): heap memory is never reused, so it is already zeroed
+        self.sp -= 1;
+        let size = self.stack[self.sp];
+        if size < 0 {
+            panic!("malloc called with negative size {}", size);
+        }
+        self.ax = self.heap_alloc(size as usize) as i64;
+    }
+
+    fn exec_free(&mut self) {
+        // free(ptr): the bump allocator does not reclaim memory
+        self.sp -= 1;
+        self.ax = 0;
+    }
+
+    /// Pops the arguments of a builtin call, first argument first
+    fn builtin_args<const N: usize>(&mut self) -> [i64; N] {
+        self.sp -= N;
+        let mut args = [0; N];
+        for (i, arg) in args.iter_mut().enumerate() {
+            *arg = self.stack[self.sp + N - 1 - i]; // arguments are pushed last-to-first
+        }
+        args
+    }
+
+    /// Checks that `len` bytes from `address` are in memory, returning the byte range
+    fn check_range(&self, address: i64, len: i64) -> std::ops::Range<usize> {
+        let len = len.max(0) as usize;
+        let start = if len == 0 { address.max(0) as usize } else { self.check_address(address, len) };
+        start..start + len
+    }
+
+    fn exec_mset(&mut self) {
+        // memset(dest, byte, n)
+        let [dest, byte, n] = self.builtin_args();
+        let range = self.check_range(dest, n);
+        self.memory[range].fill(byte as u8);
+        self.ax = dest;
+    }
+
+    fn exec_mcmp(&mut self) {
+        // memcmp(a, b, n): the difference of the first differing bytes, as unsigned chars
+        let [a, b, n] = self.builtin_args();
+        let (a, b) = (self.check_range(a, n), self.check_range(b, n));
+        self.ax = self.memory[a].iter().zip(&self.memory[b])
+            .find(|(x, y)| x != y)
+            .map_or(0, |(x, y)| *x as i64 - *y as i64);
+    }
+
+    fn exec_mcpy(&mut self) {
+        // memcpy(dest, src, n)
+        let [dest, src, n] = self.builtin_args();
+        let (to, from) = (self.check_range(dest, n), self.check_range(src, n));
+        self.memory.copy_within(from, to.start);
+        self.ax = dest;
+    }
+
+    fn exec_open(&mut self) {
+        // open(path, flags): files are only opened for reading, descriptors start after stderr
+        let [path, _flags] = self.builtin_args();
+        let path = self.c_string(path);
+        self.ax = match File::open(path) {
+            Ok(file) => {
+                let fd = (3..).find(|fd| !self.files.contains_key(fd)).unwrap();
+                self.files.insert(fd, file);
+                fd
+            }
+            Err(_) => -1,
+        };
+    }
+
+    fn exec_read(&mut self) {
+        // read(fd, buf, n): descriptor 0 reads standard input
+        let [fd, buf, n] = self.builtin_args();
+        let range = self.check_range(buf, n);
+        let result = match fd {
+            0 => std::io::stdin().read(&mut self.memory[range]),
+            _ => match self.files.get_mut(&fd) {
+                Some(file) => file.read(&mut self.memory[range]),
+                None => Err(std::io::ErrorKind::NotFound.into()),
+            },
+        };
+        self.ax = result.map_or(-1, |count| count as i64);
+    }
+
+    fn exec_clos(&mut self) {
+        // close(fd)
+        let [fd] = self.builtin_args();
+        self.ax = if self.files.remove(&fd).is_some() { 0 } else { -1 };
+    }
+
+    fn exec_alloc(&mut self, size: usize) {
+        // Reserve zeroed memory for an array, word aligned.
+        // A declaration run again in the same call, e.g. in a loop body, reuses its memory.
+        let size = size.div_ceil(WORD_SIZE) * WORD_SIZE;
+        let site = self.pc - 1;
+        let address = match self.frame_allocs.last().and_then(|allocs| allocs.get(&site)) {
+            Some(&address) => address,
+            None => {
+                if self.mem_top + size > self.heap_top {
+                    panic!("Out of memory");
+                }
+                let address = self.mem_top;
+                self.mem_top += size;
+                self.frame_allocs.last_mut().unwrap().insert(site, address);
+                address
+            }
+        };
+        self.memory[address..address + size].fill(0);
+        self.ax = address as i64;
+    }
+
+    /// Reads the NUL terminated string at `address`
+    fn c_string(&self, address: i64) -> String {
+        read_c_string(&self.memory, self.check_address(address, 1))
+    }
+
+    fn check_address(&self, address: i64, size: usize) -> usize {
+        if address <= 0 || address as usize + size > MEMORY_SIZE {
+            panic!("Invalid memory access at address {}", address);
+        }
+        address as usize
+    }
+
+    fn exec_li(&mut self) {
+        // Load a word from the address in ax
+        let addr = self.check_address(self.ax, WORD_SIZE);
+        let mut bytes = [0u8; WORD_SIZE];
+        bytes.copy_from_slice(&self.memory[addr..addr + WORD_SIZE]);
+        self.ax = i64::from_le_bytes(bytes);
+    }
+
+    fn exec_lc(&mut self) {
+        // Load a (signed) char from the address in ax
+        let addr = self.check_address(self.ax, 1);
+        self.ax = self.memory[addr] as i8 as i64;
+    }
+
+    fn exec_si(&mut self) {
+        // Store ax as a word at the address on top of the stack
+        self.sp -= 1;
+        let addr = self.check_address(self.stack[self.sp], WORD_SIZE);
+        self.memory[addr..addr + WORD_SIZE].copy_from_slice(&self.ax.to_le_bytes());
+    }
+
+    fn exec_sc(&mut self) {
+        // Store the low byte of ax at the address on top of the stack
+        self.sp -= 1;
+        let addr = self.check_address(self.stack[self.sp], 1);
+        self.memory[addr] = self.ax as u8;
+        self.ax = self.memory[addr] as i8 as i64;
+    }
+
+    fn exec_lf(&mut self) {
+        // Load a float from the address in ax, widened to a double
+        let addr = self.check_address(self.ax, 4);
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.memory[addr..addr + 4]);
+        self.ax = (f32::from_le_bytes(bytes) as f64).to_bits() as i64;
+    }
+
+    fn exec_sf(&mut self) {
+        // Store ax as a float at the address on top of the stack, keeping the rounded value
+        self.sp -= 1;
+        let addr = self.check_address(self.stack[self.sp], 4);
+        let value = f64::from_bits(self.ax as u64) as f32;
+        self.memory[addr..addr + 4].copy_from_slice(&value.to_le_bytes());
+        self.ax = (value as f64).to_bits() as i64;
     }
 
     fn exec_deref(&mut self) {
@@ -339,7 +686,7 @@ impl VM {
             if scope.contains_key(name) {
                 // Create a fake address based on scope and name
                 let fake_address = ((i + 1) * 1000) + name.len() * 10;
-                self.ax = fake_address as i32;
+                self.ax = fake_address as i64;
                 return;
             }
         }
@@ -354,25 +701,234 @@ impl VM {
     }
 }
 
+
+/// Flags, field width and precision of one printf conversion
+#[derive(Default)]
+struct FormatSpec {
+    left: bool,  // '-': pad on the right
+    plus: bool,  // '+': always print a sign
+    space: bool, // ' ': a space where a plus sign would go
+    zero: bool,  // '0': pad numbers with zeros after the sign
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    /// Prefixes the sign and pads the result to the field width
+    fn pad(&self, negative: bool, digits: &str, numeric: bool) -> String {
+        let sign = if negative { "-" } else if self.plus { "+" } else if self.space { " " } else { "" };
+        let fill = self.width.saturating_sub(sign.len() + digits.len());
+        if self.left {
+            format!("{}{}{}", sign, digits, " ".repeat(fill))
+        } else if self.zero && numeric {
+            format!("{}{}{}", sign, "0".repeat(fill), digits)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), sign, digits)
+        }
+    }
+}
+
+/// Formats a non-negative value like %e: `d.ddde+XX` with `precision` decimals
+fn exponent_form(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+/// Formats a non-negative value like %g: `precision` significant digits without trailing zeros,
+/// in exponent form only for very small or large values
+fn general_form(value: f64, precision: usize) -> String {
+    let precision = precision.max(1);
+    let rounded = format!("{:.*e}", precision - 1, value);
+    let exponent: i32 = rounded.split_once('e').unwrap().1.parse().unwrap();
+    let text = if exponent < -4 || exponent >= precision as i32 {
+        exponent_form(value, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+    };
+    let (mantissa, exponent) = text.split_at(text.find('e').unwrap_or(text.len()));
+    let mantissa = if mantissa.contains('.') { mantissa.trim_end_matches('0').trim_end_matches('.') } else { mantissa };
+    format!("{}{}", mantissa, exponent)
+}
+
+/// Reads a NUL terminated string from memory, one char per byte like string literals
+fn read_c_string(memory: &[u8], start: usize) -> String {
+    let len = memory[start..].iter().position(|&byte| byte == 0).unwrap_or(memory.len() - start);
+    memory[start..start + len].iter().map(|&byte| byte as char).collect()
+}
+
+/// Expands the %d, %i, %u, %o, %x, %c, %s, %e, %f, %g and %% conversions of a printf format string.
+/// Floating arguments are passed as the bits of a double and %s reads its string from `memory`;
+/// other conversions use up their argument but are printed as written.
+pub fn format_printf(fmt: &str, values: &[i64], memory: &[u8]) -> String {
+    let mut output = String::new();
+    let mut values = values.iter();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        let mut directive = String::from('%');
+        let mut spec = FormatSpec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            directive.push(flag);
+            chars.next();
+        }
+        while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            spec.width = spec.width * 10 + digit as usize;
+            directive.extend(chars.next());
+        }
+        if chars.peek() == Some(&'.') {
+            directive.extend(chars.next());
+            let mut precision = 0;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                precision = precision * 10 + digit as usize;
+                directive.extend(chars.next());
+            }
+            spec.precision = Some(precision);
+        }
+        // Every argument is a word, so length modifiers change nothing
+        while let Some(&modifier) = chars.peek().filter(|c| matches!(c, 'l' | 'h')) {
+            directive.push(modifier);
+            chars.next();
+        }
+
+        let conversion = match chars.next() {
+            Some('%') => {
+                output.push('%');
+                continue;
+            }
+            Some(conversion) if conversion.is_ascii_alphabetic() => conversion,
+            other => {
+                output.push_str(&directive);
+                output.extend(other);
+                continue;
+            }
+        };
+        let value = *values.next().unwrap_or_else(|| panic!("Mismatch between format string and arguments"));
+        // Only signed conversions print a sign
+        if !matches!(conversion, 'd' | 'i' | 'e' | 'E' | 'f' | 'F' | 'g' | 'G') {
+            spec.plus = false;
+            spec.space = false;
+        }
+        let text = match conversion {
+            'd' | 'i' | 'u' | 'o' | 'x' | 'X' => {
+                let mut digits = match conversion {
+                    'd' | 'i' => value.unsigned_abs().to_string(),
+                    'u' => (value as u64).to_string(),
+                    'o' => format!("{:o}", value as u64),
+                    'x' => format!("{:x}", value as u64),
+                    _ => format!("{:X}", value as u64),
+                };
+                if let Some(precision) = spec.precision {
+                    // A precision gives the minimum number of digits and disables zero padding
+                    digits = format!("{:0>1$}", digits, precision);
+                    spec.zero = false;
+                }
+                spec.pad(matches!(conversion, 'd' | 'i') && value < 0, &digits, true)
+            }
+            'c' => spec.pad(false, &(value as u8 as char).to_string(), false),
+            's' => {
+                let string = match usize::try_from(value) {
+                    Ok(0) => "(null)".to_string(),
+                    Ok(start) if start < memory.len() => read_c_string(memory, start),
+                    _ => panic!("Invalid memory access at address {}", value),
+                };
+                // A precision is the most characters to print
+                let string: String = string.chars().take(spec.precision.unwrap_or(usize::MAX)).collect();
+                spec.pad(false, &string, false)
+            }
+            'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+                let value = f64::from_bits(value as u64);
+                let precision = spec.precision.unwrap_or(6);
+                let digits = if value.is_nan() {
+                    "nan".to_string()
+                } else if value.is_infinite() {
+                    "inf".to_string()
+                } else {
+                    match conversion.to_ascii_lowercase() {
+                        'e' => exponent_form(value.abs(), precision),
+                        'f' => format!("{:.*}", precision, value.abs()),
+                        _ => general_form(value.abs(), precision),
+                    }
+                };
+                let text = spec.pad(value.is_sign_negative(), &digits, value.is_finite());
+                if conversion.is_ascii_uppercase() { text.to_uppercase() } else { text }
+            }
+            // Skipping the argument keeps the conversions after this one in step
+            _ => format!("{}{}", directive, conversion),
+        };
+        output.push_str(&text);
+    }
+    output
+}
+
+/// Compile-time information about the names visible to the code generator
+#[derive(Clone, Default)]
+struct Symbols {
+    types: HashMap<String, Type>,  // declared type of every variable and function in scope
+    addressed: HashSet<String>,    // names used with `&`; such scalars are kept in memory
+    breaks: Vec<Vec<usize>>,       // jumps out of each enclosing loop or switch, patched at its end
+    cases: Vec<HashMap<Option<i64>, usize>>, // jump to each case of each enclosing switch, None for default
+    labels: HashMap<String, usize>,  // address of each goto label in the current function
+    gotos: Vec<(String, usize)>,     // gotos to patch once the function's labels are all known
+}
+
+impl Symbols {
+    /// Returns the declared type of a name, treating unknown names as int
+    fn type_of(&self, name: &str) -> Type {
+        self.types.get(name).cloned().unwrap_or(Type::Int)
+    }
+
+    /// Whether a scalar variable lives in memory because its address is taken.
+    /// Arrays and structs always live in memory and their slot holds their address.
+    /// Its slot then holds that address under the hidden name `&name`.
+    fn is_boxed(&self, name: &str) -> bool {
+        self.addressed.contains(name) && !self.type_of(name).is_aggregate()
+    }
+}
+
 /// Converts AST nodes into VM instructions and function definitions
 /// # Argument: program - The AST nodes representing the program
 /// Returns: A tuple containing the instructions and function definitions
-pub fn generate(program: Vec<ASTNode>) -> (Vec<Instruction>, HashMap<String, Function>) {
-    let mut instructions = vec![
-        Instruction::CALL("main".to_string()),
-        Instruction::EXIT, // ← make sure EXIT happens AFTER main returns
-    ];
+pub fn generate(mut program: Vec<ASTNode>) -> (Vec<Instruction>, HashMap<String, Function>) {
+    let mut instructions = Vec::new();
     let mut functions = HashMap::new();
     let mut func_defs = Vec::new();
+    let mut globals = Symbols::default();
+    rename_shadowed(&mut program);
+
+    // Function signatures are known before any body is generated
+    for node in &program {
+        collect_addressed(node, &mut globals.addressed);
+        if let ASTNode::FuncDef { return_type, name, params, .. } = node {
+            globals.types.insert(name.clone(), Type::Function {
+                return_type: Box::new(return_type.clone()),
+                params: params.iter().map(|(ty, _)| ty.clone()).collect(),
+            });
+        }
+    }
 
-    // Separate function definitions and top-level expressions
+    // Separate function definitions and top-level expressions;
+    // globals are initialized before main is called
     for node in program {
-        if let ASTNode::FuncDef { .. } = node {
-            func_defs.push(node);
-        } else {
-            generate_node_with_push(&node, &mut instructions, true);
+        match node {
+            ASTNode::FuncDef { .. } => func_defs.push(node),
+            ASTNode::FuncDecl { .. } => {} // prototypes generate no code
+            _ => generate_node_with_push(&node, &mut instructions, true, &mut globals),
         }
     }
+    instructions.push(Instruction::CALL("main".to_string()));
+    instructions.push(Instruction::EXIT); // ← make sure EXIT happens AFTER main returns
 
     // Generate function definitions after the call
     for node in func_defs {
@@ -381,9 +937,26 @@ pub fn generate(program: Vec<ASTNode>) -> (Vec<Instruction>, HashMap<String, Fun
             
             // Create a new variable scope for the function
             instructions.push(Instruction::ENT(0)); // Will update with local variable count
+
+            let mut symbols = globals.clone();
+            for (param_type, param_name) in &params {
+                symbols.types.insert(param_name.clone(), param_type.clone());
+                if symbols.is_boxed(param_name) {
+                    // Move the argument into memory so `&param` has an address
+                    instructions.push(Instruction::ALLOC(param_type.size()));
+                    instructions.push(Instruction::DEFINE(boxed_slot(param_name)));
+                    instructions.push(Instruction::PUSH);
+                    instructions.push(Instruction::LOAD(param_name.clone()));
+                    instructions.push(store_instruction(param_type));
+                }
+            }
             
             for stmt in &body {
-                generate_node_with_push(&stmt, &mut instructions, false);
+                generate_node_with_push(stmt, &mut instructions, false, &mut symbols);
+            }
+            for (label, index) in symbols.gotos.drain(..) {
+                let target = *symbols.labels.get(&label).unwrap_or_else(|| panic!("Undefined label '{}'", label));
+                patch_jump(&mut instructions, index, target);
             }
 
             // Ensure there is a return instruction
@@ -419,53 +992,102 @@ pub fn generate(program: Vec<ASTNode>) -> (Vec<Instruction>, HashMap<String, Fun
 /// node - The AST node to generate instructions for
 /// instructions - The vector to append instructions to
 /// push_result - Whether to push the result onto the stack
-fn generate_node_with_push(node: &ASTNode, instructions: &mut Vec<Instruction>, push_result: bool) {
+/// symbols - Types of the names in scope
+fn generate_node_with_push(node: &ASTNode, instructions: &mut Vec<Instruction>, push_result: bool, symbols: &mut Symbols) {
     match node {
         ASTNode::Num(value) => {
-            instructions.push(Instruction::IMM(*value as i32));
+            instructions.push(Instruction::IMM(*value));
+            if push_result {
+                instructions.push(Instruction::PUSH);
+            }
+        }
+        ASTNode::FNum(value) => {
+            instructions.push(Instruction::FIMM(*value));
             if push_result {
                 instructions.push(Instruction::PUSH);
             }
         }
         ASTNode::Id(name) => {
-            instructions.push(Instruction::LOAD(name.clone()));
+            let ty = symbols.type_of(name);
+            if symbols.is_boxed(name) {
+                instructions.push(Instruction::LOAD(boxed_slot(name)));
+                instructions.push(load_instruction(&ty));
+            } else {
+                // Arrays and structs evaluate to their address, which is what the slot holds
+                instructions.push(Instruction::LOAD(name.clone()));
+            }
             if push_result {
                 instructions.push(Instruction::PUSH);
             }
         }
-        ASTNode::Str(_message) => {
+        ASTNode::Str(string) => {
+            instructions.push(Instruction::LoadString(string.clone()));
             if push_result {
-                panic!("Unexpected string literal used as an expression");
+                instructions.push(Instruction::PUSH);
             }
         }
         ASTNode::DeclAssign { typename, name, value } => {
+            symbols.types.insert(name.clone(), typename.clone());
             match typename {
-                Token::Char => {
-                    generate_node_with_push(value, instructions, true);
-                    instructions.push(Instruction::STORE(name.clone()));
+                _ if typename.is_aggregate() => {
+                    instructions.push(Instruction::ALLOC(typename.size()));
+                    instructions.push(Instruction::DEFINE(name.clone()));
+                    generate_initializer(name, typename, value, 0, instructions, symbols);
                 }
-                Token::CharPointer => {
-                    if let ASTNode::Str(string) = &**value {
-                        instructions.push(Instruction::LoadString(string.clone()));
-                        instructions.push(Instruction::STORE(name.clone()));
-                    } else {
-                        panic!("Invalid value for char*");
-                    }
+                _ if symbols.is_boxed(name) => {
+                    instructions.push(Instruction::ALLOC(typename.size()));
+                    instructions.push(Instruction::DEFINE(boxed_slot(name)));
+                    instructions.push(Instruction::PUSH);
+                    generate_node_with_push(value, instructions, false, symbols);
+                    instructions.push(store_instruction(typename));
                 }
                 _ => {
-                    generate_node_with_push(value, instructions, true);
-                    instructions.push(Instruction::STORE(name.clone()));
+                    generate_node_with_push(value, instructions, false, symbols);
+                    instructions.push(Instruction::DEFINE(name.clone()));
                 }
             }
         }
         ASTNode::Assign { name, value } => {
-            generate_node_with_push(value, instructions, true);
-            instructions.push(Instruction::STORE(name.clone()));
+            if symbols.is_boxed(name) {
+                let ty = symbols.type_of(name);
+                instructions.push(Instruction::LOAD(boxed_slot(name)));
+                instructions.push(Instruction::PUSH);
+                generate_node_with_push(value, instructions, false, symbols);
+                instructions.push(store_instruction(&ty));
+            } else {
+                generate_node_with_push(value, instructions, false, symbols);
+                instructions.push(Instruction::STORE(name.clone()));
+            }
+            // The assignment yields the stored value, still in ax
+            if push_result {
+                instructions.push(Instruction::PUSH);
+            }
+        }
+        ASTNode::PostfixOp { op, expr } => {
+            generate_step(op, expr, true, instructions, symbols);
+            if push_result {
+                instructions.push(Instruction::PUSH);
+            }
+        }
+        ASTNode::BinaryOp { op: Token::Comma, left, right } => {
+            // The left value is discarded
+            generate_node_with_push(left, instructions, false, symbols);
+            generate_node_with_push(right, instructions, push_result, symbols);
         }
         ASTNode::BinaryOp { op, left, right } => {
-            generate_node_with_push(left, instructions, true);
-            generate_node_with_push(right, instructions, true);
+            // The type checker converted both operands to the same type
+            let floating = expr_type(left, symbols).is_floating();
+            generate_node_with_push(left, instructions, true, symbols);
+            generate_node_with_push(right, instructions, true, symbols);
             match op {
+                Token::Add if floating => instructions.push(Instruction::FADD),
+                Token::Sub if floating => instructions.push(Instruction::FSUB),
+                Token::Mul if floating => instructions.push(Instruction::FMUL),
+                Token::Div if floating => instructions.push(Instruction::FDIV),
+                Token::Gt if floating => instructions.push(Instruction::FGT),
+                Token::Lt if floating => instructions.push(Instruction::FLT),
+                Token::Eq if floating => instructions.push(Instruction::FEQ),
+                Token::Ne if floating => instructions.push(Instruction::FNE),
                 Token::Add => instructions.push(Instruction::ADD),
                 Token::Sub => instructions.push(Instruction::SUB),
                 Token::Mul => instructions.push(Instruction::MUL),
@@ -482,24 +1104,42 @@ fn generate_node_with_push(node: &ASTNode, instructions: &mut Vec<Instruction>,
             }
         }
         ASTNode::If { cond, then_branch, else_branch } => {
-            generate_node_with_push(cond, instructions, false);
+            generate_node_with_push(cond, instructions, false, symbols);
             instructions.push(Instruction::JZ(0)); // placeholder
             let jz_index = instructions.len() - 1;
 
-            generate_node_with_push(then_branch, instructions, false);
+            generate_node_with_push(then_branch, instructions, false, symbols);
 
             if let Some(else_branch) = else_branch {
                 instructions.push(Instruction::JMP(0)); // placeholder
                 let jmp_index = instructions.len() - 1;
 
                 let else_start = instructions.len();
-                generate_node_with_push(else_branch, instructions, false);
+                generate_node_with_push(else_branch, instructions, false, symbols);
                 instructions[jz_index] = Instruction::JZ(else_start);
                 instructions[jmp_index] = Instruction::JMP(instructions.len());
             } else {
                 instructions[jz_index] = Instruction::JZ(instructions.len());
             }
         }
+        ASTNode::Cond { cond, then_branch, else_branch } => {
+            // Either branch leaves its value in ax, which is pushed once at the end
+            generate_node_with_push(cond, instructions, false, symbols);
+            instructions.push(Instruction::JZ(0)); // placeholder
+            let jz_index = instructions.len() - 1;
+            generate_node_with_push(then_branch, instructions, false, symbols);
+            instructions.push(Instruction::JMP(0)); // placeholder
+            let jmp_index = instructions.len() - 1;
+
+            let else_start = instructions.len();
+            generate_node_with_push(else_branch, instructions, false, symbols);
+            let end = instructions.len();
+            patch_jump(instructions, jz_index, else_start);
+            patch_jump(instructions, jmp_index, end);
+            if push_result {
+                instructions.push(Instruction::PUSH);
+            }
+        }
         ASTNode::FuncCall { name, args } => {
             if name == "printf" {
                 if args.is_empty() {
@@ -511,7 +1151,7 @@ fn generate_node_with_push(node: &ASTNode, instructions: &mut Vec<Instruction>,
                         let mut fmt_args = Vec::new();
                         for (i, arg) in args.iter().enumerate().skip(1).rev() {
                             let arg_name = format!("__printf_arg_{}", i);
-                            generate_node_with_push(arg, instructions, true);
+                            generate_node_with_push(arg, instructions, true, symbols);
                             // Store in a pseudo-variable slot (not actually used by VM logic — it's symbolic)
                             fmt_args.push(arg_name);
                         }
@@ -522,67 +1162,148 @@ fn generate_node_with_push(node: &ASTNode, instructions: &mut Vec<Instruction>,
             } else if name == "__block" {
                 // Handle special __block function
                 for arg in args {
-                    generate_node_with_push(arg, instructions, false);
+                    generate_node_with_push(arg, instructions, false, symbols);
                 }
             } else if name == "return" {
                 // Handle return statements
                 if !args.is_empty() {
-                    generate_node_with_push(&args[0], instructions, true);
+                    generate_node_with_push(&args[0], instructions, true, symbols);
                 } else {
                     instructions.push(Instruction::IMM(0));
                     instructions.push(Instruction::PUSH);
                 }
                 instructions.push(Instruction::RETURN);
             } else {
-                // Regular function calls
+                // Regular function calls, arguments pushed last to first
                 for arg in args.iter().rev() {
-                    // Special handling for pointer arguments
-                    match arg {
-                        ASTNode::Id(var_name) => {
-                            // For variable arguments, we use LOAD
-                            instructions.push(Instruction::LOAD(var_name.clone()));
-                            instructions.push(Instruction::PUSH);
-                        },
-                        ASTNode::UnaryOp { op: Token::Mul, expr } => {
-                            // For dereferenced pointer arguments
-                            generate_node_with_push(expr, instructions, true);
-                            instructions.push(Instruction::DEREF);
-                            instructions.push(Instruction::PUSH);
-                        },
-                        _ => {
-                            // Default handling for other argument types
-                            generate_node_with_push(arg, instructions, true);
-                        }
-                    }
+                    generate_node_with_push(arg, instructions, true, symbols);
+                }
+                match name.as_str() {
+                    "malloc" => instructions.push(Instruction::MALC),
+                    "free" => instructions.push(Instruction::FREE),
+                    "exit" => instructions.push(Instruction::EXIT), // the status is still in ax
+                    "memset" => instructions.push(Instruction::MSET),
+                    "memcmp" => instructions.push(Instruction::MCMP),
+                    "memcpy" => instructions.push(Instruction::MCPY),
+                    "open" => instructions.push(Instruction::OPEN),
+                    "read" => instructions.push(Instruction::READ),
+                    "close" => instructions.push(Instruction::CLOS),
+                    _ => instructions.push(Instruction::CALL(name.clone())),
                 }
-                instructions.push(Instruction::CALL(name.clone()));
                 if push_result {
                     instructions.push(Instruction::PUSH);
                 }                
             }
         }               
         ASTNode::Return(expr) => {
-            generate_node_with_push(expr, instructions, true); // Generate code for the return value
+            generate_node_with_push(expr, instructions, true, symbols); // Generate code for the return value
             instructions.push(Instruction::RETURN); // Emit the RETURN instruction
         }
         ASTNode::WhileLoop { condition, body } => {
             let loop_start = instructions.len();
 
-            generate_node_with_push(condition, instructions, false); // Evaluate condition
+            generate_node_with_push(condition, instructions, false, symbols); // Evaluate condition
             instructions.push(Instruction::JZ(0)); // Jump to after loop if false
             let jz_index = instructions.len() - 1;
 
+            symbols.breaks.push(Vec::new());
             for stmt in body.iter() {
-                generate_node_with_push(stmt, instructions, false);
+                generate_node_with_push(stmt, instructions, false, symbols);
             }
 
             instructions.push(Instruction::JMP(loop_start)); // Jump back to start
             let loop_end = instructions.len();
 
-            // Patch JZ with loop_end
-            if let Instruction::JZ(ref mut target) = instructions[jz_index] {
-                *target = loop_end;
+            // Patch JZ and any breaks with loop_end
+            patch_jump(instructions, jz_index, loop_end);
+            for index in symbols.breaks.pop().unwrap() {
+                patch_jump(instructions, index, loop_end);
+            }
+        }
+        ASTNode::ForLoop { init, condition, step, body } => {
+            if let Some(init) = init {
+                generate_node_with_push(init, instructions, false, symbols);
+            }
+            let loop_start = instructions.len();
+            let jz_index = condition.as_ref().map(|condition| {
+                generate_node_with_push(condition, instructions, false, symbols);
+                instructions.push(Instruction::JZ(0)); // Jump to after loop if false
+                instructions.len() - 1
+            });
+
+            symbols.breaks.push(Vec::new());
+            for stmt in body {
+                generate_node_with_push(stmt, instructions, false, symbols);
+            }
+            if let Some(step) = step {
+                generate_node_with_push(step, instructions, false, symbols);
+            }
+            instructions.push(Instruction::JMP(loop_start));
+            let loop_end = instructions.len();
+
+            for index in jz_index.into_iter().chain(symbols.breaks.pop().unwrap()) {
+                patch_jump(instructions, index, loop_end);
+            }
+        }
+        ASTNode::Switch { expr, body } => {
+            // The value is kept in a hidden slot while it is compared against each case
+            let slot = format!("switch#{}", instructions.len());
+            generate_node_with_push(expr, instructions, false, symbols);
+            instructions.push(Instruction::DEFINE(slot.clone()));
+
+            let mut values = Vec::new();
+            collect_cases(body, &mut values);
+            let mut labels = HashMap::new();
+            for value in values {
+                instructions.push(Instruction::LOAD(slot.clone()));
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::IMM(value));
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::EQ);
+                instructions.push(Instruction::JNZ(0)); // patched when the case label is reached
+                labels.insert(Some(value), instructions.len() - 1);
+            }
+            instructions.push(Instruction::JMP(0)); // to default, or past the switch if there is none
+            labels.insert(None, instructions.len() - 1);
+
+            symbols.cases.push(labels);
+            symbols.breaks.push(Vec::new());
+            for stmt in body {
+                generate_node_with_push(stmt, instructions, false, symbols);
+            }
+            let switch_end = instructions.len();
+            // Only the default jump can be left unpatched
+            for index in symbols.cases.pop().unwrap().into_values() {
+                patch_jump(instructions, index, switch_end);
+            }
+            for index in symbols.breaks.pop().unwrap() {
+                patch_jump(instructions, index, switch_end);
+            }
+        }
+        ASTNode::Case(value) => {
+            // Execution falls through into the label from the case before it
+            let target = instructions.len();
+            if let Some(index) = symbols.cases.last_mut().and_then(|labels| labels.remove(&Some(*value))) {
+                patch_jump(instructions, index, target);
+            }
+        }
+        ASTNode::Default => {
+            let target = instructions.len();
+            if let Some(index) = symbols.cases.last_mut().and_then(|labels| labels.remove(&None)) {
+                patch_jump(instructions, index, target);
             }
+        }
+        ASTNode::Label(name) => {
+            symbols.labels.insert(name.clone(), instructions.len());
+        }
+        ASTNode::Goto(name) => {
+            instructions.push(Instruction::JMP(0)); // patched at the end of the function
+            symbols.gotos.push((name.clone(), instructions.len() - 1));
+        }
+        ASTNode::Break => {
+            instructions.push(Instruction::JMP(0)); // patched at the end of the loop or switch
+            let index = instructions.len() - 1;
+            symbols.breaks.last_mut().expect("break outside of a loop or switch").push(index);
         }        
         // ASTNode::FuncCall { name, args } if name == "__block" => {
         //     for arg in args {
@@ -590,82 +1311,148 @@ fn generate_node_with_push(node: &ASTNode, instructions: &mut Vec<Instruction>,
         //     }
         // },
         ASTNode::Decl { typename, name } => {
+            symbols.types.insert(name.clone(), typename.clone());
             // Default initialize variables
             match typename {
-                Token::Int => {
-                    instructions.push(Instruction::IMM(0)); // Default value for `int`
-                    instructions.push(Instruction::STORE(name.clone()));
-                },
-                Token::Char => {
-                    instructions.push(Instruction::IMM(0)); // Default value for `char`
-                    instructions.push(Instruction::STORE(name.clone()));
+                _ if typename.is_aggregate() => {
+                    instructions.push(Instruction::ALLOC(typename.size())); // Zeroed elements
+                    instructions.push(Instruction::DEFINE(name.clone()));
+                }
+                Type::Int | Type::Char | Type::Float | Type::Double | Type::Pointer(_) if symbols.is_boxed(name) => {
+                    instructions.push(Instruction::ALLOC(typename.size())); // Zeroed memory cell
+                    instructions.push(Instruction::DEFINE(boxed_slot(name)));
                 }
-                Token::CharPointer => {
-                    instructions.push(Instruction::IMM(0)); // Default value for `char*` (null pointer)
-                    instructions.push(Instruction::STORE(name.clone()));
+                Type::Int | Type::Char | Type::Float | Type::Double | Type::Pointer(_) => {
+                    instructions.push(Instruction::IMM(0)); // Default value (null for pointers, 0.0 for floats)
+                    instructions.push(Instruction::DEFINE(name.clone()));
                 }
                 _ => panic!("Unsupported type in declaration: {:?}", typename),
             }
         },
+        ASTNode::Index { .. } | ASTNode::Member { .. } => {
+            let elem_type = generate_address(node, instructions, symbols);
+            // An element that is itself an array or struct evaluates to its address
+            if !elem_type.is_aggregate() {
+                instructions.push(load_instruction(&elem_type));
+            }
+            if push_result {
+                instructions.push(Instruction::PUSH);
+            }
+        }
+        ASTNode::MemAssign { target, value } => {
+            let elem_type = generate_address(target, instructions, symbols);
+            instructions.push(Instruction::PUSH);
+            generate_node_with_push(value, instructions, false, symbols);
+            instructions.push(store_instruction(&elem_type));
+            if push_result {
+                instructions.push(Instruction::PUSH);
+            }
+        }
         ASTNode::Block(statements) => {
             for stmt in statements {
-                generate_node_with_push(stmt, instructions, false);
+                generate_node_with_push(stmt, instructions, false, symbols);
+            }
+        },
+        ASTNode::Cast { typename, expr } => {
+            let from = expr_type(expr, symbols);
+            generate_node_with_push(expr, instructions, false, symbols);
+            match (from.is_floating(), typename.is_floating()) {
+                (true, false) if *typename != Type::Void => instructions.push(Instruction::FTI),
+                (false, true) => instructions.push(Instruction::ITF),
+                _ => {}
+            }
+            // Doubles are rounded to float precision, but floats are computed as doubles
+            if *typename == Type::Float && from != Type::Float {
+                instructions.push(Instruction::DTF);
+            }
+            if *typename == Type::Char && from != Type::Char {
+                // Keep only the low byte, sign extended like a char load
+                let shift = ((WORD_SIZE - 1) * 8) as i64;
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::IMM(shift));
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::SHL);
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::IMM(shift));
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::SHR);
+            }
+            if push_result {
+                instructions.push(Instruction::PUSH);
             }
         },
         ASTNode::UnaryOp { op, expr } => {
             match op {
                 Token::Mul => {
                     // Handle pointer dereference
-                    generate_node_with_push(expr, instructions, true);
-                    instructions.push(Instruction::DEREF);
+                    let target_type = generate_address(node, instructions, symbols);
+                    if !target_type.is_aggregate() {
+                        instructions.push(load_instruction(&target_type));
+                    }
                     if push_result {
                         instructions.push(Instruction::PUSH);
                     }
                 },
                 Token::And => {
                     // Handle address-of operator
-                    if let ASTNode::Id(name) = &**expr {
-                        instructions.push(Instruction::ADDR(name.clone()));
-                        if push_result {
-                            instructions.push(Instruction::PUSH);
+                    match &**expr {
+                        ASTNode::Id(name) if symbols.type_of(name).is_aggregate() => {
+                            instructions.push(Instruction::LOAD(name.clone()));
                         }
-                    } else {
-                        panic!("Address-of operator can only be applied to variables");
+                        _ => {
+                            generate_address(expr, instructions, symbols);
+                        }
+                    }
+                    if push_result {
+                        instructions.push(Instruction::PUSH);
                     }
                 },
-                Token::Not => {
-                    // Handle logical NOT
-                    generate_node_with_push(expr, instructions, true);
-                    instructions.push(Instruction::IMM(0));
+                Token::Sub if expr_type(expr, symbols).is_floating() => {
+                    // -x is computed as x * -1.0, so that -0.0 keeps its sign
+                    generate_node_with_push(expr, instructions, true, symbols);
+                    instructions.push(Instruction::FIMM(-1.0));
                     instructions.push(Instruction::PUSH);
-                    instructions.push(Instruction::EQ);
+                    instructions.push(Instruction::FMUL);
                     if push_result {
                         instructions.push(Instruction::PUSH);
                     }
                 },
-                // Handle other unary operators
-                Token::Inc => {
-                    if let ASTNode::Id(name) = &**expr {
-                        instructions.push(Instruction::LOAD(name.clone()));
+                Token::Sub => {
+                    // -x is computed as 0 - x
+                    instructions.push(Instruction::IMM(0));
+                    instructions.push(Instruction::PUSH);
+                    generate_node_with_push(expr, instructions, true, symbols);
+                    instructions.push(Instruction::SUB);
+                    if push_result {
                         instructions.push(Instruction::PUSH);
-                        instructions.push(Instruction::IMM(1));
+                    }
+                },
+                Token::Tilde => {
+                    // ~x is computed as x ^ -1, as in c4
+                    generate_node_with_push(expr, instructions, true, symbols);
+                    instructions.push(Instruction::IMM(-1));
+                    instructions.push(Instruction::PUSH);
+                    instructions.push(Instruction::XOR);
+                    if push_result {
                         instructions.push(Instruction::PUSH);
-                        instructions.push(Instruction::ADD);
-                        instructions.push(Instruction::STORE(name.clone()));
-                    } else {
-                        panic!("Increment operator must be applied to a variable");
                     }
                 },
-                Token::Dec => {
-                    if let ASTNode::Id(name) = &**expr {
-                        instructions.push(Instruction::LOAD(name.clone()));
+                Token::Add => generate_node_with_push(expr, instructions, push_result, symbols),
+                Token::Not => {
+                    // Handle logical NOT
+                    generate_node_with_push(expr, instructions, true, symbols);
+                    instructions.push(Instruction::IMM(0));
+                    instructions.push(Instruction::PUSH);
+                    instructions.push(Instruction::EQ);
+                    if push_result {
                         instructions.push(Instruction::PUSH);
-                        instructions.push(Instruction::IMM(1));
+                    }
+                },
+                // Handle other unary operators
+                Token::Inc | Token::Dec => {
+                    generate_step(op, expr, false, instructions, symbols);
+                    if push_result {
                         instructions.push(Instruction::PUSH);
-                        instructions.push(Instruction::SUB);
-                        instructions.push(Instruction::STORE(name.clone()));
-                    } else {
-                        panic!("Decrement operator must be applied to a variable");
                     }
                 },
                 _ => panic!("Unsupported unary operator {:?}", op),
@@ -674,3 +1461,386 @@ fn generate_node_with_push(node: &ASTNode, instructions: &mut Vec<Instruction>,
         _ => panic!("Unsupported AST node {:?}", node),
     }
 }
+
+/// Name of the hidden slot holding the address of a boxed variable
+fn boxed_slot(name: &str) -> String {
+    format!("&{}", name)
+}
+
+/// Gives each local that shadows a visible variable a slot of its own, named like `x#3`.
+/// A call keeps all its variables in one map, so an inner `x` would otherwise overwrite the outer one.
+fn rename_shadowed(program: &mut [ASTNode]) {
+    let mut renamer = Renamer { scopes: vec![HashMap::new()], count: 0 };
+    for node in program {
+        match node {
+            ASTNode::FuncDef { params, body, .. } => {
+                renamer.scopes.push(params.iter().map(|(_, name)| (name.clone(), name.clone())).collect());
+                body.iter_mut().for_each(|stmt| renamer.rename(stmt));
+                renamer.scopes.pop();
+            }
+            other => renamer.rename(other),
+        }
+    }
+}
+
+/// The slot name of each variable in scope, innermost scope last
+struct Renamer {
+    scopes: Vec<HashMap<String, String>>,
+    count: usize,
+}
+
+impl Renamer {
+    /// Brings a declared name into the innermost scope, returning its slot name
+    fn declare(&mut self, name: &str) -> String {
+        let slot = if self.scopes.iter().any(|scope| scope.contains_key(name)) {
+            self.count += 1;
+            format!("{}#{}", name, self.count)
+        } else {
+            name.to_string()
+        };
+        self.scopes.last_mut().unwrap().insert(name.to_string(), slot.clone());
+        slot
+    }
+
+    /// Returns the slot of the innermost variable with this name
+    fn resolve(&self, name: &str) -> String {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).cloned().unwrap_or_else(|| name.to_string())
+    }
+
+    /// Renames the statements of a block, whose declarations end with it
+    fn scoped(&mut self, nodes: &mut [ASTNode]) {
+        self.scopes.push(HashMap::new());
+        nodes.iter_mut().for_each(|node| self.rename(node));
+        self.scopes.pop();
+    }
+
+    fn rename(&mut self, node: &mut ASTNode) {
+        match node {
+            ASTNode::Id(name) => *name = self.resolve(name),
+            ASTNode::Assign { name, value } => {
+                self.rename(value);
+                *name = self.resolve(name);
+            }
+            ASTNode::Decl { name, .. } => *name = self.declare(name),
+            ASTNode::DeclAssign { name, value, .. } => {
+                *name = self.declare(name);
+                self.rename(value);
+            }
+            ASTNode::FuncCall { name, args } if name == "__block" => self.scoped(args),
+            ASTNode::If { cond, then_branch, else_branch } => {
+                self.rename(cond);
+                self.scoped(std::slice::from_mut(then_branch));
+                if let Some(else_branch) = else_branch {
+                    self.scoped(std::slice::from_mut(else_branch));
+                }
+            }
+            ASTNode::WhileLoop { condition: expr, body } | ASTNode::Switch { expr, body } => {
+                self.rename(expr);
+                self.scoped(body);
+            }
+            ASTNode::ForLoop { init, condition, step, body } => {
+                // A declaration in the header is scoped to the loop
+                self.scopes.push(HashMap::new());
+                for clause in [init, condition, step].into_iter().flatten() {
+                    self.rename(clause);
+                }
+                self.scoped(body);
+                self.scopes.pop();
+            }
+            ASTNode::UnaryOp { expr, .. } | ASTNode::PostfixOp { expr, .. } | ASTNode::Return(expr)
+            | ASTNode::Cast { expr, .. } | ASTNode::Member { expr, .. } | ASTNode::SizeofExpr(expr) => self.rename(expr),
+            ASTNode::BinaryOp { left, right, .. }
+            | ASTNode::Index { array: left, index: right }
+            | ASTNode::MemAssign { target: left, value: right } => {
+                self.rename(left);
+                self.rename(right);
+            }
+            ASTNode::Cond { cond, then_branch, else_branch } => {
+                self.rename(cond);
+                self.rename(then_branch);
+                self.rename(else_branch);
+            }
+            ASTNode::Block(nodes) | ASTNode::InitList(nodes) | ASTNode::FuncCall { args: nodes, .. } => {
+                nodes.iter_mut().for_each(|node| self.rename(node));
+            }
+            ASTNode::Num(_) | ASTNode::FNum(_) | ASTNode::Str(_) | ASTNode::SizeofType(_)
+            | ASTNode::Case(_) | ASTNode::Default | ASTNode::Break | ASTNode::Label(_) | ASTNode::Goto(_)
+            | ASTNode::FuncDef { .. } | ASTNode::FuncDecl { .. } => {}
+        }
+    }
+}
+
+/// Records every variable whose address is taken with `&`
+fn collect_addressed(node: &ASTNode, addressed: &mut HashSet<String>) {
+    match node {
+        ASTNode::UnaryOp { op: Token::And, expr } => {
+            if let ASTNode::Id(name) = &**expr {
+                addressed.insert(name.clone());
+            }
+            collect_addressed(expr, addressed);
+        }
+        ASTNode::UnaryOp { expr, .. } | ASTNode::PostfixOp { expr, .. } | ASTNode::Return(expr)
+        | ASTNode::Cast { expr, .. } | ASTNode::Member { expr, .. } => collect_addressed(expr, addressed),
+        ASTNode::BinaryOp { left, right, .. }
+        | ASTNode::Index { array: left, index: right }
+        | ASTNode::MemAssign { target: left, value: right } => {
+            collect_addressed(left, addressed);
+            collect_addressed(right, addressed);
+        }
+        ASTNode::Assign { value, .. } | ASTNode::DeclAssign { value, .. } => collect_addressed(value, addressed),
+        ASTNode::Cond { cond, then_branch, else_branch } => {
+            collect_addressed(cond, addressed);
+            collect_addressed(then_branch, addressed);
+            collect_addressed(else_branch, addressed);
+        }
+        ASTNode::If { cond, then_branch, else_branch } => {
+            collect_addressed(cond, addressed);
+            collect_addressed(then_branch, addressed);
+            if let Some(else_branch) = else_branch {
+                collect_addressed(else_branch, addressed);
+            }
+        }
+        ASTNode::WhileLoop { condition: cond, body } => {
+            collect_addressed(cond, addressed);
+            body.iter().for_each(|stmt| collect_addressed(stmt, addressed));
+        }
+        ASTNode::Block(nodes) | ASTNode::InitList(nodes)
+        | ASTNode::FuncCall { args: nodes, .. }
+        | ASTNode::FuncDef { body: nodes, .. } => {
+            nodes.iter().for_each(|stmt| collect_addressed(stmt, addressed));
+        }
+        // The operand of sizeof is never evaluated
+        ASTNode::Switch { expr, body } => {
+            collect_addressed(expr, addressed);
+            body.iter().for_each(|stmt| collect_addressed(stmt, addressed));
+        }
+        ASTNode::ForLoop { init, condition, step, body } => {
+            for clause in [init, condition, step].into_iter().flatten() {
+                collect_addressed(clause, addressed);
+            }
+            body.iter().for_each(|stmt| collect_addressed(stmt, addressed));
+        }
+        ASTNode::Num(_) | ASTNode::FNum(_) | ASTNode::Id(_) | ASTNode::Str(_) | ASTNode::Decl { .. }
+        | ASTNode::SizeofType(_) | ASTNode::SizeofExpr(_)
+        | ASTNode::Case(_) | ASTNode::Default | ASTNode::Break
+        | ASTNode::Label(_) | ASTNode::Goto(_) | ASTNode::FuncDecl { .. } => {}
+    }
+}
+
+/// Collects the case values of a switch body, skipping nested switches
+fn collect_cases(nodes: &[ASTNode], values: &mut Vec<i64>) {
+    for node in nodes {
+        match node {
+            ASTNode::Case(value) => values.push(*value),
+            ASTNode::If { then_branch, else_branch, .. } => {
+                collect_cases(std::slice::from_ref(then_branch), values);
+                if let Some(else_branch) = else_branch {
+                    collect_cases(std::slice::from_ref(else_branch), values);
+                }
+            }
+            ASTNode::WhileLoop { body, .. } | ASTNode::ForLoop { body, .. }
+            | ASTNode::Block(body) | ASTNode::FuncCall { args: body, .. } => {
+                collect_cases(body, values);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Generates `++x`/`--x`, or `x++`/`x--` when `postfix` is set, leaving the result in ax
+/// Pointers step by the size of what they point to, floating values by 1.0
+fn generate_step(op: &Token, expr: &ASTNode, postfix: bool, instructions: &mut Vec<Instruction>, symbols: &mut Symbols) {
+    let ty = expr_type(expr, symbols);
+    let step = match ty.target() {
+        _ if ty.is_floating() => Instruction::FIMM(1.0),
+        Some(target) => Instruction::IMM(target.size() as i64),
+        None => Instruction::IMM(1),
+    };
+    let (step_op, undo_op) = match (*op == Token::Inc, ty.is_floating()) {
+        (true, false) => (Instruction::ADD, Instruction::SUB),
+        (false, false) => (Instruction::SUB, Instruction::ADD),
+        (true, true) => (Instruction::FADD, Instruction::FSUB),
+        (false, true) => (Instruction::FSUB, Instruction::FADD),
+    };
+    match expr {
+        ASTNode::Id(name) if !symbols.is_boxed(name) => {
+            instructions.push(Instruction::LOAD(name.clone()));
+            instructions.push(Instruction::PUSH);
+            instructions.push(step.clone());
+            instructions.push(Instruction::PUSH);
+            instructions.push(step_op);
+            instructions.push(Instruction::STORE(name.clone()));
+        }
+        ASTNode::Id(_) | ASTNode::Index { .. } | ASTNode::Member { .. } | ASTNode::UnaryOp { op: Token::Mul, .. } => {
+            let ty = generate_address(expr, instructions, symbols);
+            instructions.push(Instruction::PUSH); // address to store back to
+            instructions.push(load_instruction(&ty));
+            instructions.push(Instruction::PUSH);
+            instructions.push(step.clone());
+            instructions.push(Instruction::PUSH);
+            instructions.push(step_op);
+            instructions.push(store_instruction(&ty));
+        }
+        _ => panic!("Increment/decrement operator must be applied to a variable"),
+    }
+    if postfix {
+        // Recover the old value from the stored one, as c4 does
+        instructions.push(Instruction::PUSH);
+        instructions.push(step);
+        instructions.push(Instruction::PUSH);
+        instructions.push(undo_op);
+    }
+}
+
+/// Points an already emitted jump at `target`
+fn patch_jump(instructions: &mut [Instruction], index: usize, target: usize) {
+    match &mut instructions[index] {
+        Instruction::JMP(address) | Instruction::JZ(address) | Instruction::JNZ(address) => *address = target,
+        other => panic!("Instruction {:?} is not a jump", other),
+    }
+}
+
+/// Works out the type of an expression from the declarations in scope
+fn expr_type(node: &ASTNode, symbols: &Symbols) -> Type {
+    match node {
+        ASTNode::Num(_) => Type::Int,
+        ASTNode::FNum(_) => Type::Double,
+        ASTNode::Str(_) => Type::Char.pointer_to(),
+        ASTNode::Id(name) | ASTNode::Assign { name, .. } => symbols.type_of(name),
+        ASTNode::Index { array, .. } => expr_type(array, symbols).target().cloned().unwrap_or(Type::Int),
+        ASTNode::MemAssign { target, .. } => expr_type(target, symbols),
+        ASTNode::UnaryOp { op: Token::Mul, expr } => expr_type(expr, symbols).target().cloned().unwrap_or(Type::Int),
+        ASTNode::UnaryOp { op: Token::And, expr } => expr_type(expr, symbols).pointer_to(),
+        ASTNode::UnaryOp { op: Token::Inc | Token::Dec, expr } | ASTNode::PostfixOp { expr, .. } => expr_type(expr, symbols),
+        ASTNode::BinaryOp { op: Token::Comma, right, .. } => expr_type(right, symbols),
+        ASTNode::BinaryOp { op: Token::Add | Token::Sub, left, right } => {
+            let (left, right) = (expr_type(left, symbols), expr_type(right, symbols));
+            match (left.target(), right.target()) {
+                (Some(_), Some(_)) => Type::Int, // pointer difference
+                (Some(target), None) => target.clone().pointer_to(),
+                (None, Some(target)) => target.clone().pointer_to(),
+                (None, None) if left.is_floating() => left,
+                (None, None) => Type::Int,
+            }
+        }
+        ASTNode::BinaryOp { op: Token::Mul | Token::Div, left, .. } | ASTNode::UnaryOp { op: Token::Sub | Token::Add, expr: left } => {
+            let ty = expr_type(left, symbols);
+            if ty.is_floating() { ty } else { Type::Int }
+        }
+        ASTNode::FuncCall { name, .. } => match symbols.type_of(name) {
+            Type::Function { return_type, .. } => *return_type,
+            _ => Type::Int,
+        },
+        ASTNode::Cond { then_branch, .. } => expr_type(then_branch, symbols),
+        ASTNode::Cast { typename, .. } => typename.clone(),
+        ASTNode::Member { expr, name } => member_of(&expr_type(expr, symbols), name).ty,
+        _ => Type::Int,
+    }
+}
+
+/// Looks up a member of a struct type; the type checker has already vouched for it
+fn member_of(ty: &Type, name: &str) -> Member {
+    let member = match ty {
+        Type::Struct(aggregate) => aggregate.member(name),
+        _ => None,
+    };
+    member.unwrap_or_else(|| panic!("No member '{}' in '{}'", name, ty))
+}
+
+/// Picks the load instruction matching a value's type
+fn load_instruction(ty: &Type) -> Instruction {
+    match ty {
+        Type::Char => Instruction::LC,
+        Type::Float => Instruction::LF,
+        _ => Instruction::LI,
+    }
+}
+
+/// Picks the store instruction matching a value's type
+fn store_instruction(ty: &Type) -> Instruction {
+    match ty {
+        Type::Char => Instruction::SC,
+        Type::Float => Instruction::SF,
+        _ => Instruction::SI,
+    }
+}
+
+/// Generates instructions storing an initializer into array `name` at byte `offset`.
+/// The type checker has already rejected initializers that do not fit the type.
+fn generate_initializer(name: &str, ty: &Type, init: &ASTNode, offset: usize, instructions: &mut Vec<Instruction>, symbols: &mut Symbols) {
+    match (ty, init) {
+        (Type::Array(elem, _), ASTNode::InitList(values)) => {
+            // Remaining elements stay zero, as ALLOC clears the memory
+            for (i, value) in values.iter().enumerate() {
+                generate_initializer(name, elem, value, offset + i * elem.size(), instructions, symbols);
+            }
+        }
+        (Type::Array(elem, _), ASTNode::Str(string)) if **elem == Type::Char => {
+            for (i, byte) in literal_bytes(string).into_iter().enumerate() {
+                generate_initializer(name, elem, &ASTNode::Num(byte as i64), offset + i, instructions, symbols);
+            }
+        }
+        (Type::Array(..), other) => unreachable!("Invalid initializer for array '{}': {:?}", name, other),
+        (Type::Struct(aggregate), ASTNode::InitList(values)) => {
+            // Members are initialized in order; a union only has its first member initialized
+            for (member, value) in aggregate.members().iter().zip(values) {
+                generate_initializer(name, &member.ty, value, offset + member.offset, instructions, symbols);
+            }
+        }
+        (Type::Struct(_), other) => unreachable!("Invalid initializer for struct '{}': {:?}", name, other),
+        (_, value) => {
+            instructions.push(Instruction::LOAD(name.to_string()));
+            instructions.push(Instruction::PUSH);
+            instructions.push(Instruction::IMM(offset as i64));
+            instructions.push(Instruction::PUSH);
+            instructions.push(Instruction::ADD);
+            instructions.push(Instruction::PUSH);
+            generate_node_with_push(value, instructions, false, symbols);
+            instructions.push(store_instruction(ty));
+        }
+    }
+}
+
+/// Generates instructions leaving the address of a memory lvalue in ax
+/// Returns: the type of the value stored at that address
+fn generate_address(node: &ASTNode, instructions: &mut Vec<Instruction>, symbols: &mut Symbols) -> Type {
+    match node {
+        ASTNode::Id(name) if symbols.is_boxed(name) => {
+            instructions.push(Instruction::LOAD(boxed_slot(name)));
+            symbols.type_of(name)
+        }
+        ASTNode::Index { array, index } => {
+            // Unknown bases index by whole words
+            let elem_type = expr_type(array, symbols).target().cloned().unwrap_or(Type::Int);
+            let elem_size = elem_type.size();
+            generate_node_with_push(array, instructions, true, symbols);
+            generate_node_with_push(index, instructions, true, symbols);
+            if elem_size > 1 {
+                instructions.push(Instruction::IMM(elem_size as i64));
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::MUL);
+                instructions.push(Instruction::PUSH);
+            }
+            instructions.push(Instruction::ADD);
+            elem_type
+        }
+        ASTNode::UnaryOp { op: Token::Mul, expr } => {
+            let target_type = expr_type(expr, symbols).target().cloned().unwrap_or(Type::Int);
+            generate_node_with_push(expr, instructions, false, symbols);
+            target_type
+        }
+        ASTNode::Member { expr, name } => {
+            // A struct evaluates to its address, so the member is at a fixed offset from it
+            let member = member_of(&expr_type(expr, symbols), name);
+            generate_node_with_push(expr, instructions, false, symbols);
+            if member.offset > 0 {
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::IMM(member.offset as i64));
+                instructions.push(Instruction::PUSH);
+                instructions.push(Instruction::ADD);
+            }
+            member.ty
+        }
+        _ => panic!("Expression is not addressable: {:?}", node),
+    }
+}
@@ -3,9 +3,16 @@
 mod vm;
 mod lexer;
 mod parser;
+mod types;
+mod sema;
+mod typeck;
+mod preprocessor;
 
 use lexer::{Lexer};
+use preprocessor::Preprocessor;
 use parser::*;
+use sema::Analyzer;
+use typeck::TypeChecker;
 use vm::{VM, generate};  // Removed unused import: Instruction
 
 use std::env;
@@ -15,12 +22,34 @@ use std::process;
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: c4_rust <source_file.c>");
-        process::exit(1);
+    // Options come before or after the source file, each takes its value joined or separate
+    let mut preprocessor = Preprocessor::new();
+    let mut filename = None;
+    let mut options = args[1..].iter();
+    while let Some(arg) = options.next() {
+        let Some(flag) = ["-I", "-D", "-U"].into_iter().find(|flag| arg.starts_with(flag)) else {
+            filename = Some(arg);
+            continue;
+        };
+        let value = match &arg[2..] {
+            "" => options.next().map(String::as_str),
+            joined => Some(joined),
+        };
+        let Some(value) = value else {
+            eprintln!("Error: {} expects an argument", flag);
+            process::exit(1);
+        };
+        match flag {
+            "-I" => preprocessor.include_dir(value),
+            "-D" => preprocessor.define(value),
+            _ => preprocessor.undefine(value),
+        }
     }
 
-    let filename = &args[1];
+    let Some(filename) = filename else {
+        eprintln!("Usage: c4_rust [-I dir] [-D name[=value]] [-U name]... <source_file.c>");
+        process::exit(1);
+    };
     let source_code = match fs::read_to_string(filename) {
         Ok(content) => content,
         Err(err) => {
@@ -30,12 +59,37 @@ fn main() {
     };
     // println!("Successfully read {} bytes from {}", source_code.len(), filename);
 
+    // Expand macros before the lexer sees the source
+    let source_code = match preprocessor.preprocess(&source_code, filename) {
+        Ok(expanded) => expanded,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("Error: {}", error);
+            }
+            process::exit(1);
+        }
+    };
+
     let mut lexer = Lexer::new(&source_code);
+    lexer.file = Some(filename.clone());
     let tokens = lexer.tokenize();
+    if !lexer.errors.is_empty() {
+        for error in &lexer.errors {
+            eprintln!("Error: {}", error);
+        }
+        process::exit(1);
+    }
     // println!("Lexing successful. Found {} tokens.", tokens.len());
 
     let mut parser = Parser::new(tokens);
+    parser.locations = lexer.locations;
     let ast_nodes = parser.parse_program();
+    if !parser.errors.is_empty() {
+        for error in &parser.errors {
+            eprintln!("Error: {}", error);
+        }
+        process::exit(1);
+    }
     // println!("Parsing successful. Found {} nodes.", ast_nodes.len());
     
     // for (i, node) in ast_nodes.iter().enumerate() {
@@ -45,6 +99,27 @@ fn main() {
     //     }
     // }
 
+    // Report name errors before any code is generated
+    if let Err(errors) = Analyzer::new().analyze(&ast_nodes) {
+        for error in errors {
+            eprintln!("Error: {}", error);
+        }
+        process::exit(1);
+    }
+
+    // Make conversions and pointer scaling explicit, reporting type errors
+    let mut checker = TypeChecker::new();
+    let ast_nodes = checker.check_program(ast_nodes);
+    for warning in &checker.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if !checker.errors.is_empty() {
+        for error in &checker.errors {
+            eprintln!("Error: {}", error);
+        }
+        process::exit(1);
+    }
+
     let (instructions, functions) = generate(ast_nodes);
 
     // Validate we have the main function before running
@@ -55,4 +130,8 @@ fn main() {
 
     let mut vm = VM::new(instructions, functions);
     let _result = vm.run();
+    if let Some(error) = &vm.error {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
 }
\ No newline at end of file
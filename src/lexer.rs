@@ -12,17 +12,21 @@ Operators like ==, <=, ||
 */
 
 /// Represents the different types of tokens in the C language
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
 	Num(i64),	//Numeric literal
+	FNum(f64),	//Floating literal
 	Id(String),	//Identifier
 	Str(String), //String literals
 
 	//Keywords
 	Char, 
-	CharPointer,
 	Else, Enum, If, Int, Return, Sizeof, While,
 	Void, Float, Double, Short, Long,
+	Struct, Union,
+	Switch, Case, Default, Break,
+	Typedef, Goto, For,
 
 	//Operators
 	Assign,  // =
@@ -47,6 +51,8 @@ pub enum Token {
 	Mod,     // %
 	Inc,     // ++
 	Dec,     // --
+	Dot,     // .
+	Arrow,   // ->
 
 	//Symbols and Punctuation
 	Semicolon,  // ;
@@ -68,12 +74,20 @@ pub enum Token {
 	Unknown(char), //unknown character
 }
 
+/// String literals hold one char per byte, so escapes like `\xff` keep their exact value
+pub fn literal_bytes(string: &str) -> Vec<u8> {
+	string.chars().map(|c| c as u8).collect()
+}
+
 /// Lexical analyzer that converts source code into tokens
 pub struct Lexer<'a> {
 	pub chars: std::str::Chars<'a>, //iterator over the source
 	pub current_char: Option<char>, //current character
 	pub line: usize, //current line number
+	pub file: Option<String>, //file named by the last #line marker
 	pub peeked: Option<char>, //one-character lookahead
+	pub errors: Vec<String>, //malformed literals
+	pub locations: Vec<String>, //where each token from tokenize starts, for parser diagnostics
 }
 
 impl<'a> Lexer<'a> {
@@ -86,13 +100,17 @@ impl<'a> Lexer<'a> {
             chars,
             current_char,
             line: 1,
+            file: None,
             peeked: None,
+            errors: Vec::new(),
+            locations: Vec::new(),
         }
     }
 
     /// Advance to the next character
     fn advance(&mut self) {
         self.current_char = self.chars.next();
+        self.peeked = None;
     }
 
     /// Peek the next character without advancing
@@ -124,13 +142,16 @@ impl<'a> Lexer<'a> {
                     self.advance();
                 }
                 Some('#') => {
-                    // Skip until end of line
+                    // Skip until end of line, following #line markers from the preprocessor
+                    let mut directive = String::new();
                     while let Some(c) = self.current_char {
                         if c == '\n' {
                             break;
                         }
+                        directive.push(c);
                         self.advance();
                     }
+                    self.line_marker(&directive);
                 }
                 Some('/') => {
                     if self.peek() == Some('/') {
@@ -151,6 +172,147 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Applies a `#line N "file"` marker, which names the line after it
+    fn line_marker(&mut self, directive: &str) {
+        let mut parts = directive[1..].trim_start().splitn(3, ' ');
+        if parts.next() != Some("line") {
+            return;
+        }
+        match parts.next().and_then(|n| n.parse::<usize>().ok()) {
+            Some(0) => self.error("Line number out of range in #line directive".to_string()),
+            Some(line) => {
+                self.line = line - 1; // the newline ending the marker moves to line N
+                if let Some(file) = parts.next() {
+                    self.file = Some(file.trim().trim_matches('"').to_string());
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Names the current line as `file:line`, or `line N` before any #line marker
+    fn location(&self) -> String {
+        match &self.file {
+            Some(file) => format!("{}:{}", file, self.line),
+            None => format!("line {}", self.line),
+        }
+    }
+
+    /// Records an error at the current line
+    fn error(&mut self, message: String) {
+        let message = format!("{}: {}", self.location(), message);
+        self.errors.push(message);
+    }
+
+    /// Reads the escape sequence after a backslash, leaving its last character current
+    fn escape(&mut self) -> Option<char> {
+        let c = self.current_char?;
+        let value = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'a' => '\x07',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '\'' | '"' | '?' | '\\' => c,
+            '0'..='7' => {
+                // Up to three octal digits
+                let mut value = c.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match self.peek().and_then(|d| d.to_digit(8)) {
+                        Some(digit) => {
+                            self.consume_peek();
+                            value = value * 8 + digit;
+                        }
+                        None => break,
+                    }
+                }
+                if value > 0xFF {
+                    self.error(format!("Octal escape sequence out of range: '\\{:o}'", value));
+                }
+                char::from(value as u8)
+            }
+            'x' => {
+                // As many hex digits as follow
+                let mut digits = String::new();
+                while let Some(digit) = self.peek().filter(|d| d.is_ascii_hexdigit()) {
+                    self.consume_peek();
+                    digits.push(digit);
+                }
+                let value = u32::from_str_radix(&digits, 16).unwrap_or(u32::MAX);
+                if digits.is_empty() {
+                    self.error("\\x used with no following hex digits".into());
+                } else if value > 0xFF {
+                    self.error(format!("Hex escape sequence out of range: '\\x{}'", digits));
+                }
+                char::from(value as u8)
+            }
+            other => {
+                self.error(format!("Unknown escape sequence '\\{}'", other));
+                other
+            }
+        };
+        Some(value)
+    }
+
+    /// Converts a decimal, octal or hex constant with an optional u/l/ll suffix.
+    /// Every constant becomes a 64-bit value, so the suffix only has to be valid.
+    fn integer_constant(&mut self, text: &str) -> i64 {
+        let (radix, body) = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hex) => (16, hex),
+            None if text.starts_with('0') => (8, &text[1..]),
+            None => (10, text),
+        };
+        let split = body.find(|c: char| !c.is_digit(if radix == 16 { 16 } else { 10 })).unwrap_or(body.len());
+        let (digits, suffix) = body.split_at(split);
+
+        // u may come before or after l or ll, and ll must not mix case
+        let length = suffix.strip_prefix(['u', 'U']).or_else(|| suffix.strip_suffix(['u', 'U'])).unwrap_or(suffix);
+        if !matches!(length, "" | "l" | "L" | "ll" | "LL") {
+            self.error(format!("Invalid suffix '{}' on integer constant", suffix));
+            return 0;
+        }
+        if radix == 16 && digits.is_empty() {
+            self.error(format!("Invalid integer constant '{}'", text));
+            return 0;
+        }
+        if let Some(digit) = digits.chars().find(|&d| radix == 8 && d >= '8') {
+            self.error(format!("Invalid digit '{}' in octal constant", digit));
+            return 0;
+        }
+
+        // Without u a decimal constant must fit a signed long long
+        let value = u64::from_str_radix(if digits.is_empty() { "0" } else { digits }, radix).ok();
+        let unsigned = length.len() != suffix.len() || radix != 10;
+        match value {
+            Some(value) if unsigned || value <= i64::MAX as u64 => value as i64,
+            _ => {
+                self.error(format!("Integer constant '{}' is too large for its type", text));
+                0
+            }
+        }
+    }
+
+    /// Converts a decimal floating constant with an optional f/l suffix.
+    /// Every constant is held as a double; an f suffix rounds it to float precision first.
+    fn floating_constant(&mut self, text: &str) -> f64 {
+        let (body, single) = match text.strip_suffix(['f', 'F']) {
+            Some(body) => (body, true),
+            None => (text.strip_suffix(['l', 'L']).unwrap_or(text), false),
+        };
+        let valid = body.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+            && !body.ends_with(['e', 'E', '+', '-']);
+        match body.parse::<f64>() {
+            Ok(value) if valid && single => value as f32 as f64,
+            Ok(value) if valid => value,
+            _ => {
+                self.error(format!("Invalid floating constant '{}'", text));
+                0.0
+            }
+        }
+    }
+
     /// Determines if an identifier is a keyword or normal identifier
     fn keyword_or_id(ident: &str) -> Token {
         match ident {
@@ -167,6 +329,15 @@ impl<'a> Lexer<'a> {
             "double"  => Token::Double,
             "short"   => Token::Short,
             "long"    => Token::Long,
+            "struct"  => Token::Struct,
+            "union"   => Token::Union,
+            "switch"  => Token::Switch,
+            "case"    => Token::Case,
+            "default" => Token::Default,
+            "break"   => Token::Break,
+            "typedef" => Token::Typedef,
+            "goto"    => Token::Goto,
+            "for"     => Token::For,
             _ => Token::Id(ident.to_string()),
         }
     }
@@ -174,6 +345,7 @@ impl<'a> Lexer<'a> {
     /// Returns the next token from the source code
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace_and_comments(); //handle white space and comments
+        let leading_dot = self.current_char == Some('.') && self.peek().is_some_and(|d| d.is_ascii_digit());
 
         match self.current_char {
             None => Token::EOF,
@@ -189,52 +361,33 @@ impl<'a> Lexer<'a> {
 						break;
 					}
 				}
+				// The preprocessor leaves __LINE__ to the lexer, which follows its #line markers
+				if ident == "__LINE__" {
+					return Token::Num(self.line as i64);
+				}
 				Lexer::keyword_or_id(&ident)
 			}
 
-			Some(c) if c.is_ascii_digit() => { //handle numeric literals
-				let mut num: i64 = 0;
-			
-				if c == '0' {
-					self.advance();
-					match self.current_char {
-						Some('x') | Some('X') => { //Hexadecimal
-							self.advance();
-							while let Some(c) = self.current_char {
-								if c.is_ascii_hexdigit() {
-									num = num * 16
-										+ c.to_digit(16).unwrap() as i64;
-									self.advance();
-								} else {
-									break;
-								}
-							}
-						}
-						Some(c2) if c2.is_ascii_digit() => { //Octal
-							while let Some(c) = self.current_char {
-								if c >= '0' && c <= '7' {
-									num = num * 8 + (c as i64 - '0' as i64);
-									self.advance();
-								} else {
-									break;
-								}
-							}
-						}
-						_ => { //Zero
-							num = 0;
-						}
-					}
-				} else { //Decimal
-					while let Some(c) = self.current_char {
-						if c.is_ascii_digit() {
-							num = num * 10 + (c as i64 - '0' as i64);
-							self.advance();
-						} else {
-							break;
-						}
+			Some(c) if c.is_ascii_digit() || leading_dot => { //handle numeric literals
+				// Read the whole constant, suffix and exponent sign included, before checking it
+				let mut text = String::new();
+				while let Some(c) = self.current_char {
+					let exponent_sign = matches!(c, '+' | '-')
+						&& text.ends_with(['e', 'E'])
+						&& !text.starts_with("0x") && !text.starts_with("0X");
+					if c.is_ascii_alphanumeric() || c == '_' || c == '.' || exponent_sign {
+						text.push(c);
+						self.advance();
+					} else {
+						break;
 					}
 				}
-				Token::Num(num)
+				let hex = text.starts_with("0x") || text.starts_with("0X");
+				if text.contains('.') || (!hex && text.contains(['e', 'E'])) {
+					Token::FNum(self.floating_constant(&text))
+				} else {
+					Token::Num(self.integer_constant(&text))
+				}
 			}
 
 			Some('\'') => {
@@ -243,13 +396,8 @@ impl<'a> Lexer<'a> {
 				let c = match self.current_char {
 					Some('\\') => {
 						self.advance();
-						match self.current_char {
-							Some('n') => '\n',
-							Some('t') => '\t',
-							Some('\'') => '\'',
-							Some('\"') => '\"',
-							Some('\\') => '\\',
-							Some(other) => other,
+						match self.escape() {
+							Some(c) => c,
 							None => return Token::Unknown('\\'),
 						}
 					}
@@ -261,7 +409,11 @@ impl<'a> Lexer<'a> {
 				match self.current_char {
 					Some('\'') => {
 						self.advance(); //skip closing '
-						Token::Num(c as i64)
+						// Byte values are read as a signed char, so '\xff' is -1
+						match u8::try_from(c) {
+							Ok(byte) => Token::Num(byte as i8 as i64),
+							Err(_) => Token::Num(c as i64),
+						}
 					}
 					_ => Token::Unknown('\''),
 				}
@@ -278,18 +430,15 @@ impl<'a> Lexer<'a> {
 					}
 					if c == '\\' {
 						self.advance();
-						match self.current_char {
-							Some('n') => string.push('\n'),
-							Some('t') => string.push('\t'),
-							Some('r') => string.push('\r'),
-							Some('"') => string.push('\"'),
-							Some('\'') => string.push('\''),
-							Some('\\') => string.push('\\'),
-							Some(unknown) => string.push(unknown),
+						match self.escape() {
+							Some(c) => string.push(c),
 							None => return Token::Unknown('\\'),
 						}
-					} else {
+					} else if c.is_ascii() {
 						string.push(c);
+					} else {
+						// Other characters are stored as their UTF-8 bytes
+						string.extend(c.to_string().bytes().map(char::from));
 					}
 					self.advance();
 				}
@@ -344,11 +493,10 @@ impl<'a> Lexer<'a> {
 					}
 					'-' => {
 						self.advance();
-						if self.current_char == Some('-') {
-							self.advance();
-							Token::Dec
-						} else {
-							Token::Sub
+						match self.current_char {
+							Some('-') => { self.advance(); Token::Dec }
+							Some('>') => { self.advance(); Token::Arrow }
+							_ => Token::Sub,
 						}
 					}
 					'&' => {
@@ -370,6 +518,7 @@ impl<'a> Lexer<'a> {
 						}
 					}
 					'*' => { self.advance(); Token::Mul }
+					'.' => { self.advance(); Token::Dot }
 					'/' => { self.advance(); Token::Div }
 					'%' => { self.advance(); Token::Mod }
 					'^' => { self.advance(); Token::Xor }
@@ -401,6 +550,9 @@ impl<'a> Lexer<'a> {
         let mut tokens = Vec::new();
 
         loop {
+            // Note where the token starts, before a string literal looks past newlines for another
+            self.skip_whitespace_and_comments();
+            self.locations.push(self.location());
             let token = self.next_token();
             if token == Token::EOF {
                 tokens.push(Token::EOF);
@@ -2,4 +2,8 @@
 
 pub mod vm;
 pub mod parser;
-pub mod lexer;
\ No newline at end of file
+pub mod lexer;
+pub mod types;
+pub mod sema;
+pub mod typeck;
+pub mod preprocessor;
\ No newline at end of file
@@ -23,6 +23,9 @@ Conditional parsing - Ternary conditional "? : "
 
 /* TEMPORARY LEXER OUTPUT*/
 use crate::lexer::Token;
+use crate::types::{Aggregate, Type};
+use std::collections::HashMap;
+use std::rc::Rc;
 
 // #[derive(Debug, Clone, PartialEq)]
 // pub enum Token {
@@ -43,6 +46,7 @@ use crate::lexer::Token;
 #[derive(Debug, Clone, PartialEq)]
 pub enum ASTNode {
 	Num(i64),                //Number
+	FNum(f64),               //Floating number
 	Id(String),              //Identifier
 	Str(String),
 	Return(Box<ASTNode>),
@@ -51,6 +55,10 @@ pub enum ASTNode {
 		op: Token,
 		expr: Box<ASTNode>,
 	},
+	PostfixOp {
+		op: Token, // Inc or Dec, yielding the value from before the update
+		expr: Box<ASTNode>,
+	},
 	BinaryOp {
 		op: Token,
 		left: Box<ASTNode>,
@@ -61,11 +69,17 @@ pub enum ASTNode {
 		args: Vec<ASTNode>,
 	},
 	FuncDef {
-		return_type: Token,
+		return_type: Type,
 		name: String,
-		params: Vec<(Token, String)>, // e.g., int x, float y
+		params: Vec<(Type, String)>, // e.g., int x, char *s
 		body: Vec<ASTNode>,
 	},
+	FuncDecl {
+		return_type: Type,
+		name: String,
+		params: Vec<(Type, String)>, // names may be empty in a prototype
+		variadic: bool,              // ends in `, ...`
+	},
 	Assign {
 		name: String,
 		value: Box<ASTNode>,
@@ -81,18 +95,52 @@ pub enum ASTNode {
 		else_branch: Option<Box<ASTNode>>,
 	},
 	Decl {
-		typename: Token,
+		typename: Type,
 		name: String,
 	},
 	DeclAssign {
-		typename: Token,
+		typename: Type,
 		name: String,
-		value: Box<ASTNode>,
+		value: Box<ASTNode>, // InitList or Str for arrays
 	},
 	WhileLoop {
         condition: Box<ASTNode>,
         body: Vec<ASTNode>,
     },
+	ForLoop {
+		init: Option<Box<ASTNode>>,      // expression or declaration
+		condition: Option<Box<ASTNode>>, // missing means loop forever
+		step: Option<Box<ASTNode>>,
+		body: Vec<ASTNode>,
+	},
+	InitList(Vec<ASTNode>),  // {1, 2, 3}
+	Index {
+		array: Box<ASTNode>,
+		index: Box<ASTNode>,
+	},
+	MemAssign {
+		target: Box<ASTNode>, // lvalue living in memory, e.g. a[i] or *p
+		value: Box<ASTNode>,
+	},
+	Cast {
+		typename: Type,
+		expr: Box<ASTNode>,
+	},
+	SizeofType(Type),          // sizeof(int), folded to a Num by the type checker
+	SizeofExpr(Box<ASTNode>),  // sizeof x, the operand is never evaluated
+	Member {
+		expr: Box<ASTNode>, // s.name; p->name is parsed as (*p).name
+		name: String,
+	},
+	Switch {
+		expr: Box<ASTNode>,
+		body: Vec<ASTNode>, // statements with Case and Default labels among them
+	},
+	Case(i64),  // `case 3:` label, folded to its constant value
+	Default,    // `default:` label
+	Break,
+	Label(String), // `name:`, visible throughout its function
+	Goto(String),
 }
 
 /// Parser for converting tokens into an Abstract Syntax Tree
@@ -100,6 +148,11 @@ pub struct Parser {
 	tokens: Vec<Token>,
 	pos: usize,
 	pending_decls: Vec<ASTNode>,
+	enums: HashMap<String, (i64, usize)>, // enumerator constants and where they were declared
+	tags: HashMap<String, (Rc<Aggregate>, Option<usize>)>, // struct/union tags and where their body is
+	typedefs: HashMap<String, Type>, // typedef names, which start declarations and casts like type keywords
+	pub locations: Vec<String>, // source line of each token from the lexer, empty if unknown
+	pub errors: Vec<String>, // diagnostics for malformed declarations, with their source line
 }
 
 impl Parser {
@@ -107,8 +160,13 @@ impl Parser {
 	pub fn new(tokens: Vec<Token>) -> Self {
 		Parser {
 			tokens,
+			locations: Vec::new(),
 			pos: 0,
 			pending_decls: Vec::new(),
+			enums: HashMap::new(),
+			tags: HashMap::new(),
+			typedefs: HashMap::new(),
+			errors: Vec::new(),
 		}
 	}
 
@@ -124,6 +182,22 @@ impl Parser {
 		}
 	}
 
+	/// Prefixes a diagnostic with the current token's line, when the lexer recorded it
+	fn at_location(&self, message: String) -> String {
+		match self.locations.get(self.pos).or(self.locations.last()) {
+			Some(location) => format!("{}: {}", location, message),
+			None => message,
+		}
+	}
+
+	/// Records a diagnostic at the current token's line; backtracking may report the same one twice
+	fn error(&mut self, message: String) {
+		let message = self.at_location(message);
+		if !self.errors.contains(&message) {
+			self.errors.push(message);
+		}
+	}
+
 	/// Expects a specific token and advances if found, panics otherwise
 	fn expect(&mut self, expected: Token) {
         if self.current() == Some(&expected) {
@@ -144,6 +218,11 @@ impl Parser {
 				self.advance();
 				Some(node)
 			}
+			Some(Token::FNum(value)) => {
+				let node = ASTNode::FNum(*value);
+				self.advance();
+				Some(node)
+			}
 			Some(Token::Id(name)) => {
 				let name = name.clone();
 				self.advance();
@@ -168,18 +247,25 @@ impl Parser {
 					} else {
 						None
 					}
+				} else if let Some((value, _)) = self.enums.get(&name) {
+					Some(ASTNode::Num(*value))
 				} else {
 					Some(ASTNode::Id(name))
 				}
 			}
 			Some(Token::Str(s)) => {
-				let s = s.clone();
+				let mut s = s.clone();
 				self.advance();
+				// Adjacent literals form one string: "abc" "def" is "abcdef"
+				while let Some(Token::Str(next)) = self.current() {
+					s.push_str(next);
+					self.advance();
+				}
 				Some(ASTNode::Str(s))
 			}
 			Some(Token::LParen) => {
 				self.advance();
-				let expr = self.parse_expr();
+				let expr = self.parse_comma_expr();
 				if self.current() == Some(&Token::RParen) {
 					self.advance();
 					expr
@@ -191,23 +277,91 @@ impl Parser {
 		}
 	}
 
-	/// Parses unary operations like -x, *x, &x, !x, ++x, --x
+	/// Parses unary operations like -x, +x, ~x, *x, &x, !x, ++x, --x, sizeof x
 	pub fn parse_unary(&mut self) -> Option<ASTNode> {
 		match self.current() {
-			Some(Token::Sub) | Some(Token::Mul) | Some(Token::And) |
+			Some(Token::Sizeof) => {
+				self.advance();
+				// `sizeof(type)` is told apart from `sizeof (expr)` by the type keyword
+				let is_type = self.current() == Some(&Token::LParen) && self.starts_type(1);
+				if is_type {
+					self.advance(); // consume '('
+					let typename = self.parse_type()?;
+					self.expect(Token::RParen);
+					Some(ASTNode::SizeofType(typename))
+				} else {
+					self.parse_unary().map(|expr| ASTNode::SizeofExpr(Box::new(expr)))
+				}
+			}
+			Some(Token::LParen) if self.starts_type(1) => {
+				// A type keyword after '(' makes this a cast rather than grouping
+				self.advance(); // consume '('
+				let typename = self.parse_type()?;
+				self.expect(Token::RParen);
+				self.parse_unary().map(|expr| ASTNode::Cast {
+					typename,
+					expr: Box::new(expr),
+				})
+			}
+			Some(Token::Sub) if matches!(self.tokens.get(self.pos + 1), Some(Token::Num(_) | Token::FNum(_))) => {
+				// Negative literals like -1 are constants, as in c4
+				self.advance();
+				match self.parse_unary()? {
+					ASTNode::Num(value) => Some(ASTNode::Num(value.wrapping_neg())),
+					ASTNode::FNum(value) => Some(ASTNode::FNum(-value)),
+					expr => Some(ASTNode::UnaryOp { op: Token::Sub, expr: Box::new(expr) }),
+				}
+			}
+			Some(Token::Sub) | Some(Token::Add) | Some(Token::Tilde) | Some(Token::Mul) | Some(Token::And) |
 			Some(Token::Not) | Some(Token::Inc) | Some(Token::Dec) => {
 				let op = self.current().cloned().unwrap();
 				self.advance();
-				if let Some(expr) = self.parse_unary() {
-					Some(ASTNode::UnaryOp {
-						op,
-						expr: Box::new(expr),
-					})
-				} else {
-					None
+				self.parse_unary().map(|expr| ASTNode::UnaryOp {
+					op,
+					expr: Box::new(expr),
+				})
+			}
+			_ => self.parse_postfix(),
+		}
+	}
+
+	/// Parses postfix operations on a primary expression, like a[i], s.x, p->x and i++
+	pub fn parse_postfix(&mut self) -> Option<ASTNode> {
+		let mut node = self.parse_primary()?;
+
+		loop {
+			match self.current() {
+				Some(Token::LBracket) => {
+					self.advance(); // consume '['
+					let index = self.parse_comma_expr()?;
+					if self.current() != Some(&Token::RBracket) {
+						return None;
+					}
+					self.advance(); // consume ']'
+					node = ASTNode::Index {
+						array: Box::new(node),
+						index: Box::new(index),
+					};
 				}
+				Some(Token::Dot) | Some(Token::Arrow) => {
+					if self.current() == Some(&Token::Arrow) {
+						node = ASTNode::UnaryOp { op: Token::Mul, expr: Box::new(node) };
+					}
+					self.advance(); // consume '.' or '->'
+					let name = match self.current()? {
+						Token::Id(name) => name.clone(),
+						_ => return None,
+					};
+					self.advance();
+					node = ASTNode::Member { expr: Box::new(node), name };
+				}
+				Some(Token::Inc) | Some(Token::Dec) => {
+					let op = self.current().cloned().unwrap();
+					self.advance();
+					node = ASTNode::PostfixOp { op, expr: Box::new(node) };
+				}
+				_ => return Some(node),
 			}
-			_ => self.parse_primary(),
 		}
 	}
 
@@ -278,8 +432,11 @@ impl Parser {
 				// 	body.push(stmt);
 				if let Some(stmt) = self.parse_if()
 				.or_else(|| self.parse_while())
+				.or_else(|| self.parse_for())
+				.or_else(|| self.parse_switch())
+				.or_else(|| self.parse_label_or_jump())
 				.or_else(|| self.parse_decl())
-				.or_else(|| self.parse_expr())
+				.or_else(|| self.parse_comma_expr())
 				{
 					match stmt {
 						ASTNode::Block(stmts) => {
@@ -318,21 +475,28 @@ impl Parser {
 				return Some(ASTNode::Return(Box::new(ASTNode::Num(0)))); // Return 0 as default
 			}
 			
-			let expr = self.parse_expr()?; // Parse the return expression
+			let expr = self.parse_comma_expr()?; // Parse the return expression
 			if self.current() == Some(&Token::Semicolon) {
 				self.advance(); // Consume ';'
 			}
 			Some(ASTNode::Return(Box::new(expr)))
 		} else {
+			let snapshot = self.pos;
 			let stmt = self.parse_func_def()
-				.or_else(|| self.parse_if())
+				.or_else(|| {
+					self.pos = snapshot; // a failed function definition may have consumed `int x`
+					self.parse_if()
+				})
 				.or_else(|| self.parse_while()) // ADD THIS LINE
+				.or_else(|| self.parse_for())
+				.or_else(|| self.parse_switch())
+				.or_else(|| self.parse_label_or_jump())
 				.or_else(|| self.parse_decl())
-				.or_else(|| self.parse_expr())?;
+				.or_else(|| self.parse_comma_expr())?;
 	
 			if self.current() == Some(&Token::Semicolon) {
 				match &stmt {
-					ASTNode::If { .. } | ASTNode::WhileLoop { .. } => {
+					ASTNode::If { .. } | ASTNode::WhileLoop { .. } | ASTNode::ForLoop { .. } | ASTNode::Switch { .. } => {
 						// Do NOT consume ';' — block handles it
 					}
 					_ => {
@@ -356,7 +520,7 @@ impl Parser {
 		}
 		self.advance(); // consume '('
 	
-		let cond = self.parse_expr()?;
+		let cond = self.parse_comma_expr()?;
 	
 		if self.current() != Some(&Token::RParen) {
 			return None;
@@ -383,7 +547,7 @@ impl Parser {
 	    if self.current() == Some(&Token::While) {
 	        self.advance(); // Consume 'while'
 	        self.expect(Token::LParen); // Expect '('
-	        let condition = self.parse_expr()?; // Parse condition
+	        let condition = self.parse_comma_expr()?; // Parse condition
 	        self.expect(Token::RParen); // Expect ')'
 
 	        // Parse the loop body
@@ -401,53 +565,381 @@ impl Parser {
 	    }
 	}	
 
+	/// Parses for loops; each of the three header clauses may be empty
+	pub fn parse_for(&mut self) -> Option<ASTNode> {
+		if self.current() != Some(&Token::For) {
+			return None;
+		}
+		self.advance(); // Consume 'for'
+		self.expect(Token::LParen);
+
+		let init = if self.current() == Some(&Token::Semicolon) {
+			self.advance();
+			None
+		} else if self.starts_type(0) {
+			Some(Box::new(self.parse_decl()?)) // consumes the ';'
+		} else {
+			let init = self.parse_comma_expr()?;
+			self.expect(Token::Semicolon);
+			Some(Box::new(init))
+		};
+
+		let condition = if self.current() == Some(&Token::Semicolon) {
+			None
+		} else {
+			Some(Box::new(self.parse_comma_expr()?))
+		};
+		self.expect(Token::Semicolon);
+
+		let step = if self.current() == Some(&Token::RParen) {
+			None
+		} else {
+			Some(Box::new(self.parse_comma_expr()?))
+		};
+		self.expect(Token::RParen);
+
+		let body = match self.current() {
+			Some(Token::LBrace) => match self.parse_block()? {
+				ASTNode::Block(statements) => statements,
+				_ => return None,
+			},
+			Some(Token::Semicolon) => {
+				self.advance(); // an empty body, as in `for (;;);`
+				Vec::new()
+			}
+			_ => vec![self.parse_stmt()?], // a single statement without braces
+		};
+		Some(ASTNode::ForLoop { init, condition, step, body })
+	}
+
+	/// Parses switch statements
+	pub fn parse_switch(&mut self) -> Option<ASTNode> {
+		if self.current() != Some(&Token::Switch) {
+			return None;
+		}
+		self.advance(); // Consume 'switch'
+		self.expect(Token::LParen);
+		let expr = self.parse_comma_expr()?;
+		self.expect(Token::RParen);
+
+		let body = match self.parse_block()? {
+			ASTNode::Block(statements) => statements,
+			_ => return None,
+		};
+		Some(ASTNode::Switch {
+			expr: Box::new(expr),
+			body,
+		})
+	}
+
+	/// Parses `case N:`, `default:`, `name:`, `break` and `goto name`
+	fn parse_label_or_jump(&mut self) -> Option<ASTNode> {
+		match self.current()? {
+			Token::Case => {
+				self.advance(); // Consume 'case'
+				let expr = self.parse_binary(0)?;
+				let value = match const_eval(&expr) {
+					Some(value) => value,
+					None => {
+						self.error("case label does not reduce to an integer constant".to_string());
+						return None;
+					}
+				};
+				self.expect(Token::Colon);
+				Some(ASTNode::Case(value))
+			}
+			Token::Default => {
+				self.advance(); // Consume 'default'
+				self.expect(Token::Colon);
+				Some(ASTNode::Default)
+			}
+			Token::Break => {
+				self.advance(); // Consume 'break'; the caller consumes the ';'
+				Some(ASTNode::Break)
+			}
+			Token::Goto => {
+				self.advance(); // Consume 'goto'
+				let name = match self.current()? {
+					Token::Id(name) => name.clone(),
+					_ => return None,
+				};
+				self.advance();
+				Some(ASTNode::Goto(name))
+			}
+			Token::Id(name) if self.tokens.get(self.pos + 1) == Some(&Token::Colon) => {
+				let name = name.clone();
+				self.advance();
+				self.advance(); // Consume ':'
+				Some(ASTNode::Label(name))
+			}
+			_ => None,
+		}
+	}
+
+	/// Checks whether the token `offset` places ahead begins a type name
+	fn starts_type(&self, offset: usize) -> bool {
+		match self.tokens.get(self.pos + offset) {
+			Some(Token::Enum | Token::Struct | Token::Union) => true,
+			Some(Token::Id(name)) => self.typedefs.contains_key(name),
+			Some(token) => Type::from_token(token).is_some(),
+			None => false,
+		}
+	}
+
+	/// Parses a base type: a type keyword, a typedef name, an enum, struct or union specifier
+	fn parse_base_type(&mut self) -> Option<Type> {
+		match self.current() {
+			Some(Token::Enum) => return self.parse_enum(),
+			Some(Token::Struct | Token::Union) => return self.parse_struct(),
+			Some(Token::Id(name)) => {
+				let ty = self.typedefs.get(name)?.clone();
+				self.advance();
+				return Some(ty);
+			}
+			_ => {}
+		}
+		let base = Type::from_token(self.current()?)?;
+		self.advance();
+		Some(base)
+	}
+
+	/// Parses a base type followed by any number of `*`
+	fn parse_type(&mut self) -> Option<Type> {
+		let base = self.parse_base_type()?;
+		Some(self.parse_pointers(base))
+	}
+
+	/// Parses `struct [tag] [{ members }]`, or the same for a union
+	/// A tag used before its body refers to an incomplete type, completed later
+	fn parse_struct(&mut self) -> Option<Type> {
+		let is_union = self.current() == Some(&Token::Union);
+		self.advance(); // Consume `struct` or `union`
+
+		let tag = match self.current() {
+			Some(Token::Id(tag)) => {
+				let tag = tag.clone();
+				self.advance();
+				Some(tag)
+			}
+			_ => None,
+		};
+		let has_body = self.current() == Some(&Token::LBrace);
+
+		let aggregate = match &tag {
+			Some(tag) => {
+				let (aggregate, body) = self.tags.entry(tag.clone())
+					.or_insert_with(|| (Aggregate::new(Some(tag.clone()), is_union), None))
+					.clone();
+				if aggregate.is_union != is_union {
+					self.error(format!("'{}' defined as wrong kind of tag", tag));
+				}
+				// Backtracking may parse the same body twice; only a different one is a clash
+				if has_body && body.is_some_and(|pos| pos != self.pos) {
+					self.error(format!("Redefinition of '{}'", aggregate));
+					// Lay the second body out on its own so earlier uses keep the first
+					Aggregate::new(Some(tag.clone()), is_union)
+				} else {
+					if has_body {
+						self.tags.insert(tag.clone(), (aggregate.clone(), Some(self.pos)));
+					}
+					aggregate
+				}
+			}
+			None if has_body => Aggregate::new(None, is_union),
+			None => return None,
+		};
+		if !has_body {
+			return Some(Type::Struct(aggregate));
+		}
+		self.advance(); // Consume `{`
+
+		let mut members = Vec::new();
+		while self.current() != Some(&Token::RBrace) {
+			let base = self.parse_base_type()?;
+			loop {
+				let ty = self.parse_pointers(base.clone());
+				let name = match self.current()? {
+					Token::Id(name) => name.clone(),
+					_ => return None,
+				};
+				self.advance();
+				let ty = self.parse_array_dims(ty)?;
+				if matches!(&ty, Type::Struct(inner) if !inner.is_complete()) {
+					self.error(format!("Member '{}' has incomplete type '{}'", name, ty));
+				}
+				members.push((ty, name));
+				if self.current() == Some(&Token::Comma) {
+					self.advance();
+				} else {
+					break;
+				}
+			}
+			self.expect(Token::Semicolon);
+		}
+		self.expect(Token::RBrace);
+
+		if let Err(message) = aggregate.define(members) {
+			self.error(message);
+		}
+		Some(Type::Struct(aggregate))
+	}
+
+	/// Parses `enum [tag] [{ A, B = 5, C }]`, recording each enumerator's value
+	/// Enumerators count up from 0, or from the last explicit value
+	fn parse_enum(&mut self) -> Option<Type> {
+		self.advance(); // Consume `enum`
+		if let Some(Token::Id(_)) = self.current() {
+			self.advance(); // the tag names nothing beyond int
+		}
+		if self.current() != Some(&Token::LBrace) {
+			return Some(Type::Int);
+		}
+		self.advance(); // Consume `{`
+
+		let mut value = 0;
+		while self.current() != Some(&Token::RBrace) {
+			let name = match self.current()? {
+				Token::Id(name) => name.clone(),
+				_ => return None,
+			};
+			self.advance();
+			if self.current() == Some(&Token::Assign) {
+				self.advance(); // Consume `=`
+				let expr = self.parse_binary(0)?;
+				value = match const_eval(&expr) {
+					Some(value) => value,
+					None => {
+						self.error(format!("Enumerator value for '{}' is not an integer constant", name));
+						return None;
+					}
+				};
+			}
+			// Backtracking may parse the same enum twice; only a different position is a clash
+			let pos = self.pos;
+			match self.enums.insert(name.clone(), (value, pos)) {
+				Some((_, previous)) if previous != pos => self.error(format!("Redeclaration of enumerator '{}'", name)),
+				_ => {}
+			}
+			value += 1;
+
+			if self.current() == Some(&Token::Comma) {
+				self.advance(); // trailing comma is allowed
+			} else {
+				break;
+			}
+		}
+		self.expect(Token::RBrace);
+		Some(Type::Int)
+	}
+
+	/// Wraps a type in one pointer level per `*` found
+	fn parse_pointers(&mut self, mut ty: Type) -> Type {
+		while self.current() == Some(&Token::Mul) {
+			self.advance(); // Consume `*`
+			ty = ty.pointer_to();
+		}
+		ty
+	}
+
+	/// Parses the `[n][m]...` dimensions following a declarator name
+	/// Sizes are integer constant expressions like `MAX` or `2*3`;
+	/// an empty first dimension (`int a[]`) is recorded as size 0
+	fn parse_array_dims(&mut self, elem: Type) -> Option<Type> {
+		let mut dims = Vec::new();
+		while self.current() == Some(&Token::LBracket) {
+			self.advance(); // Consume `[`
+			if self.current() == Some(&Token::RBracket) {
+				dims.push(0); // size comes from the initializer
+			} else {
+				let expr = self.parse_binary(0)?;
+				match const_eval(&expr) {
+					Some(size) if size >= 0 => dims.push(size as usize),
+					Some(_) => {
+						self.error("Size of array is negative".to_string());
+						return None;
+					}
+					None => {
+						self.error("Array size is not an integer constant".to_string());
+						return None;
+					}
+				}
+			}
+			if self.current() == Some(&Token::RBracket) {
+				self.advance();
+			} else {
+				self.error(format!("Expected ']' in array declarator, found {:?}", self.current()));
+				return None;
+			}
+		}
+		Some(dims.into_iter().rev().fold(elem, |ty, n| Type::Array(Box::new(ty), n)))
+	}
+
 	/// Parses variable declarations
 	pub fn parse_decl(&mut self) -> Option<ASTNode> {
-	    let typename = match self.current()? {
-	        Token::Int | Token::Char => self.current()?.clone(),
+	    if self.current() == Some(&Token::Typedef) {
+	        return self.parse_typedef();
+	    }
+	    let base = match self.current()? {
+	        Token::Int | Token::Char | Token::Float | Token::Double | Token::Void | Token::Enum | Token::Struct | Token::Union => self.parse_base_type()?,
+	        Token::Id(name) if self.typedefs.contains_key(name) => self.parse_base_type()?,
 	        _ => return None,
 	    };
 
-	    self.advance(); // Move past the type (e.g., `char`)
-
-	    // Check for pointer (`*`)
-	    let is_pointer = if self.current() == Some(&Token::Mul) {
-	        self.advance(); // Consume `*`
-	        true
-	    } else {
-	        false
-	    };
-
 	    let mut decls = Vec::new();
 
+	    // A bare `enum { ... };` or `struct s { ... };` declares no variables
+	    if self.current() == Some(&Token::Semicolon) {
+	        self.advance();
+	        return Some(ASTNode::Block(decls));
+	    }
+
 	    loop {
+	        // Each declarator carries its own `*`s, as in `int *p, x;`
+	        let mut typename = self.parse_pointers(base.clone());
+
 	        let name = match self.current()? {
 	            Token::Id(n) => n.clone(),
 	            _ => return None,
 	        };
 	        self.advance(); // Consume the identifier
 
+	        // Check for array declarator
+	        if self.current() == Some(&Token::LBracket) {
+	            typename = self.parse_array_dims(typename)?;
+	        }
+
 	        // Check for assignment
 	        if self.current() == Some(&Token::Assign) {
 	            self.advance(); // Consume `=`
-	            let value = self.parse_expr()?;
+	            let value = if typename.is_aggregate() {
+	                self.parse_initializer()?
+	            } else {
+	                self.parse_expr()?
+	            };
+	            if let Type::Array(elem, 0) = typename {
+	                let size = match &value {
+	                    ASTNode::InitList(values) => values.len(),
+	                    ASTNode::Str(s) => s.chars().count() + 1, // room for the '\0'
+	                    _ => {
+	                        self.error(format!("Invalid initializer for array '{}'", name));
+	                        return None;
+	                    }
+	                };
+	                typename = Type::Array(elem, size);
+	            }
 	            decls.push(ASTNode::DeclAssign {
-	                typename: if is_pointer {
-	                    Token::CharPointer // Use a custom token for `char*`
-	                } else {
-	                    typename.clone()
-	                },
+	                typename,
 	                name,
 	                value: Box::new(value),
 	            });
 	        } else {
+	            if let Type::Array(_, 0) = typename {
+	                self.error(format!("Array size missing for '{}'", name));
+	                return None;
+	            }
 	            // Variable declaration without assignment
 	            decls.push(ASTNode::Decl {
-	                typename: if is_pointer {
-	                    Token::CharPointer
-	                } else {
-	                    typename.clone()
-	                },
+	                typename,
 	                name,
 	            });
 	        }
@@ -465,15 +957,65 @@ impl Parser {
 
 	    Some(ASTNode::Block(decls))
 	}
+
+	/// Parses `typedef int *intp, row[3];`, recording each name as an alias
+	/// Declares no variables, so the result is an empty block
+	fn parse_typedef(&mut self) -> Option<ASTNode> {
+		self.advance(); // Consume `typedef`
+		let base = self.parse_base_type()?;
+
+		loop {
+			let ty = self.parse_pointers(base.clone());
+			let name = match self.current()? {
+				Token::Id(name) => name.clone(),
+				_ => return None,
+			};
+			self.advance();
+			let ty = self.parse_array_dims(ty)?;
+			// A conflicting redefinition keeps the first type
+			match self.typedefs.get(&name) {
+				Some(previous) if *previous != ty => self.error(format!("Conflicting types for typedef '{}'", name)),
+				_ => {
+					self.typedefs.insert(name, ty);
+				}
+			}
+
+			match self.current() {
+				Some(Token::Comma) => self.advance(),
+				Some(Token::Semicolon) => {
+					self.advance();
+					break;
+				}
+				_ => return None,
+			}
+		}
+		Some(ASTNode::Block(Vec::new()))
+	}
+
+	/// Parses an array or struct initializer: a brace list `{1, 2, 3}`, a string literal or a plain expression
+	fn parse_initializer(&mut self) -> Option<ASTNode> {
+		if self.current() != Some(&Token::LBrace) {
+			return self.parse_expr();
+		}
+		self.advance(); // Consume `{`
+
+		let mut values = Vec::new();
+		while self.current() != Some(&Token::RBrace) {
+			values.push(self.parse_initializer()?); // nested lists for multi-dimensional arrays
+			if self.current() == Some(&Token::Comma) {
+				self.advance(); // trailing comma is allowed
+			} else {
+				break;
+			}
+		}
+		self.expect(Token::RBrace);
+
+		Some(ASTNode::InitList(values))
+	}
 	
 	/// Parses function definitions
 	pub fn parse_func_def(&mut self) -> Option<ASTNode> {	
-		let return_type = match self.current()? {
-			Token::Int | Token::Char | Token::Float | Token::Double |
-			Token::Void | Token::Short | Token::Long => self.current()?.clone(),
-			_ => return None,
-		};
-		self.advance();
+		let return_type = self.parse_type()?;
 	
 		let name = match self.current()? {
 			Token::Id(name) => name.clone(),
@@ -487,46 +1029,42 @@ impl Parser {
 		self.advance();
 	
 		let mut params = Vec::new();
+		let mut variadic = false;
+
+		// `int main(void)` takes no parameters
+		if self.current() == Some(&Token::Void) && self.tokens.get(self.pos + 1) == Some(&Token::RParen) {
+			self.advance();
+		}
 	
 		while self.current() != Some(&Token::RParen) {
-			// Get parameter type
-			let param_type = match self.current()? {
-				Token::Int | Token::Char | Token::Float | Token::Double |
-				Token::Void | Token::Short | Token::Long => self.current()?.clone(),
-				_ => {
-					return None;
-				}
-			};
-			self.advance();
-			
-			// Check for pointer (*) after type
-			let mut is_pointer = false;
-			if self.current() == Some(&Token::Mul) {
-				is_pointer = true;
-				self.advance();
+			// `...` after the named parameters
+			if !params.is_empty() && (0..3).all(|i| self.tokens.get(self.pos + i) == Some(&Token::Dot)) {
+				self.pos += 3;
+				variadic = true;
+				break;
 			}
+
+			// Get parameter type, including any pointer levels
+			let mut param_type = self.parse_type()?;
 	
-			// Get parameter name
+			// Get parameter name, which a prototype may leave out
 			let param_name = match self.current()? {
-				Token::Id(name) => name.clone(),
+				Token::Id(name) => {
+					let name = name.clone();
+					self.advance();
+					name
+				}
+				Token::Comma | Token::RParen | Token::LBracket => String::new(),
 				_ => {
 					return None;
 				}
 			};
-			self.advance();
-			
-			// Store the appropriate type (original or pointer version)
-			if is_pointer {
-				// Use CharPointer for char* parameters
-				if param_type == Token::Char {
-					params.push((Token::CharPointer, param_name));
-				} else {
-					// For other pointer types (not fully implemented yet)
-					params.push((param_type, param_name));
-				}
-			} else {
-				params.push((param_type, param_name));
+
+			// Array parameters decay to pointers: `int a[]` is `int *a`
+			if let Type::Array(elem, _) = self.parse_array_dims(param_type.clone())? {
+				param_type = Type::Pointer(elem);
 			}
+			params.push((param_type, param_name));
 	
 			if self.current() == Some(&Token::Comma) {
 				self.advance();
@@ -539,8 +1077,14 @@ impl Parser {
 			return None;
 		}
 		self.advance();
+
+		// A prototype declares the function without defining it
+		if self.current() == Some(&Token::Semicolon) {
+			self.advance();
+			return Some(ASTNode::FuncDecl { return_type, name, params, variadic });
+		}
 	
-		if self.current() != Some(&Token::LBrace) {
+		if self.current() != Some(&Token::LBrace) || variadic || params.iter().any(|(_, name)| name.is_empty()) {
 			return None;
 		}
 		self.advance();
@@ -556,8 +1100,9 @@ impl Parser {
 			
 			// Try to parse a statement - wrap in a match to handle failures gracefully
 			match self.parse_if()
+				.or_else(|| self.parse_label_or_jump())
 				.or_else(|| self.parse_decl())
-				.or_else(|| self.parse_expr())
+				.or_else(|| self.parse_comma_expr())
 				.or_else(|| self.parse_stmt()) {
 				Some(stmt) => {
 					body.push(stmt);
@@ -567,8 +1112,8 @@ impl Parser {
 				},
 				None => {
 					// In case of failure, try to skip to the next statement
-					println!("Warning: Failed to parse statement at position {}, skipping.", snapshot);
 					self.pos = snapshot;
+					println!("Warning: {}", self.at_location("Failed to parse statement, skipping.".to_string()));
 					
 					// Skip until semicolon or right brace to recover
 					while self.current() != Some(&Token::Semicolon) && 
@@ -608,15 +1153,21 @@ impl Parser {
 					name,
 					value: Box::new(value),
 				});
+			} else if let ASTNode::Index { .. } | ASTNode::Member { .. } | ASTNode::UnaryOp { op: Token::Mul, .. } = node {
+				let value = self.parse_expr()?;
+				return Some(ASTNode::MemAssign {
+					target: Box::new(node),
+					value: Box::new(value),
+				});
 			} else {
-				//Assignment target must be an identifier
+				//Assignment target must be an identifier, array element, member or dereference
 				return None;
 			}
 		}
 
 		if self.current() == Some(&Token::Cond) { //Token::Cond is '?'
     	    self.advance();
-    	    let then_branch = self.parse_expr()?;
+    	    let then_branch = self.parse_comma_expr()?;
 
     	    if self.current() != Some(&Token::Colon) { //':' is expected
     	        return None;
@@ -633,6 +1184,22 @@ impl Parser {
 		Some(node)
 	}
 
+	/// Parses a full expression: assignments joined by the comma operator,
+	/// which evaluates its left operand, discards it and yields the right one
+	pub fn parse_comma_expr(&mut self) -> Option<ASTNode> {
+		let mut node = self.parse_expr()?;
+		while self.current() == Some(&Token::Comma) {
+			self.advance(); // Consume ','
+			let right = self.parse_expr()?;
+			node = ASTNode::BinaryOp {
+				op: Token::Comma,
+				left: Box::new(node),
+				right: Box::new(right),
+			};
+		}
+		Some(node)
+	}
+
 	/// Parses a complete program
 	pub fn parse_program(&mut self) -> Vec<ASTNode> {
 		let mut nodes = Vec::new();
@@ -702,3 +1269,53 @@ impl Parser {
         Some(ASTNode::Block(stmts))
     }
 }
+
+/// Folds an integer constant expression, as needed for enumerator values and case labels
+/// Returns None if the expression refers to anything but constants
+pub fn const_eval(node: &ASTNode) -> Option<i64> {
+	match node {
+		ASTNode::Num(value) => Some(*value),
+		ASTNode::UnaryOp { op, expr } => {
+			let value = const_eval(expr)?;
+			match op {
+				Token::Sub => Some(value.wrapping_neg()),
+				Token::Add => Some(value),
+				Token::Not => Some((value == 0) as i64),
+				Token::Tilde => Some(!value),
+				_ => None,
+			}
+		}
+		ASTNode::BinaryOp { op, left, right } => {
+			let (l, r) = (const_eval(left)?, const_eval(right)?);
+			match op {
+				Token::Add => Some(l.wrapping_add(r)),
+				Token::Sub => Some(l.wrapping_sub(r)),
+				Token::Mul => Some(l.wrapping_mul(r)),
+				Token::Div => l.checked_div(r),
+				Token::Mod => l.checked_rem(r),
+				Token::Shl => Some(l.wrapping_shl(r as u32)),
+				Token::Shr => Some(l.wrapping_shr(r as u32)),
+				Token::And => Some(l & r),
+				Token::Or => Some(l | r),
+				Token::Xor => Some(l ^ r),
+				Token::Lt => Some((l < r) as i64),
+				Token::Gt => Some((l > r) as i64),
+				Token::Le => Some((l <= r) as i64),
+				Token::Ge => Some((l >= r) as i64),
+				Token::Eq => Some((l == r) as i64),
+				Token::Ne => Some((l != r) as i64),
+				Token::Lan => Some((l != 0 && r != 0) as i64),
+				Token::Lor => Some((l != 0 || r != 0) as i64),
+				_ => None,
+			}
+		}
+		ASTNode::Cond { cond, then_branch, else_branch } => {
+			if const_eval(cond)? != 0 {
+				const_eval(then_branch)
+			} else {
+				const_eval(else_branch)
+			}
+		}
+		_ => None,
+	}
+}
@@ -0,0 +1,942 @@
+// Preprocessor file
+
+/*
+Runs on the source text before the lexer.
+Handles:
+Line continuations,
+Object-like and function-like #define,
+#undef,
+Stringification (#) and token pasting (##),
+#include with search paths,
+Conditional compilation (#if, #ifdef, #ifndef, #elif, #else, #endif),
+Built-in standard headers declaring the VM builtins,
+Predefined macros (__FILE__, __DATE__, __TIME__, __C4_RUST__)
+__LINE__ is left for the lexer, which knows the line of every token.
+Directive lines are replaced by empty lines, and included text is
+wrapped in #line markers, so the lexer can track the original lines.
+*/
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How deeply #include may nest before it is treated as runaway recursion
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+/// Standard headers bundled with the compiler, used when no file of that name is found
+const BUILTIN_HEADERS: [(&str, &str); 5] = [
+	("stdio.h", "#ifndef __STDIO_H\n#define __STDIO_H\nint printf(char *format, ...);\n#endif\n"),
+	("stdlib.h", "#ifndef __STDLIB_H\n#define __STDLIB_H\nvoid *malloc(int size);\nvoid free(void *ptr);\nvoid exit(int status);\n#endif\n"),
+	("string.h", "#ifndef __STRING_H\n#define __STRING_H\nvoid *memset(void *s, int c, int n);\nint memcmp(void *a, void *b, int n);\nvoid *memcpy(void *dest, void *src, int n);\n#endif\n"),
+	("memory.h", "#include <string.h>\n"),
+	("fcntl.h", "#ifndef __FCNTL_H\n#define __FCNTL_H\n#define O_RDONLY 0\nint open(char *path, int flags);\nint read(int fd, char *buf, int n);\nint close(int fd);\n#endif\n"),
+];
+
+/// Predefined macros whose value depends on where they are used
+const DYNAMIC_MACROS: [&str; 2] = ["__FILE__", "__LINE__"];
+
+/// Operators of #if expressions by precedence, loosest binding first
+const BINARY_OPERATORS: [&[&str]; 10] = [
+	&["||"], &["&&"], &["|"], &["^"], &["&"],
+	&["==", "!="], &["<", ">", "<=", ">="], &["<<", ">>"], &["+", "-"], &["*", "/", "%"],
+];
+
+/// Multi-character punctuators, longest first, kept whole so pasting can build them
+const PUNCTUATORS: [&str; 12] = ["##", "==", "!=", "<=", ">=", "<<", ">>", "++", "--", "->", "&&", "||"];
+
+/// Kinds of preprocessing tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+	Ident,
+	Number,
+	Literal,     // string or character literal
+	Punct,
+	Newline,
+	Placemarker, // stands in for an empty argument next to ##
+}
+
+/// A preprocessing token with enough context to write it back out
+#[derive(Debug, Clone)]
+struct PPToken {
+	kind: Kind,
+	text: String,
+	space: bool,       // preceded by whitespace
+	line: usize,
+	hide: Vec<String>, // macros that must not expand this token again
+}
+
+impl PPToken {
+	fn is(&self, kind: Kind, text: &str) -> bool {
+		self.kind == kind && self.text == text
+	}
+}
+
+/// A macro definition, params is None for object-like macros
+#[derive(Debug, Clone)]
+struct Macro {
+	params: Option<Vec<String>>,
+	body: Vec<PPToken>,
+}
+
+impl Macro {
+	/// Two definitions are the same if their parameters and spelling match
+	fn same_as(&self, other: &Macro) -> bool {
+		self.params == other.params
+			&& self.body.len() == other.body.len()
+			&& self.body.iter().zip(&other.body).all(|(a, b)| a.text == b.text && (a.space == b.space || a.kind == Kind::Newline))
+	}
+
+	fn param_index(&self, token: &PPToken) -> Option<usize> {
+		match (&self.params, token.kind) {
+			(Some(params), Kind::Ident) => params.iter().position(|p| *p == token.text),
+			_ => None,
+		}
+	}
+}
+
+/// State of one #if ... #endif block
+#[derive(Debug, Clone, Copy)]
+struct Conditional {
+	line: usize,
+	active: bool,    // lines in the current group are kept
+	taken: bool,     // some group was kept already, or the whole block is skipped
+	seen_else: bool,
+}
+
+/// Evaluates the tokens of an #if once macros and defined() are replaced
+struct Evaluator<'a> {
+	tokens: &'a [PPToken],
+	pos: usize,
+	dead: usize, // depth of operands that are parsed but not evaluated, like 0 && x
+}
+
+impl Evaluator<'_> {
+	fn peek(&self) -> Option<&PPToken> {
+		self.tokens.get(self.pos)
+	}
+
+	fn expect(&mut self, text: &str) -> Result<(), String> {
+		match self.peek() {
+			Some(t) if t.is(Kind::Punct, text) => {
+				self.pos += 1;
+				Ok(())
+			}
+			Some(t) => Err(format!("Expected '{}' in #if expression, found '{}'", text, t.text)),
+			None => Err(format!("Expected '{}' at end of #if expression", text)),
+		}
+	}
+
+	/// Parses a full expression and checks nothing is left over
+	fn evaluate(&mut self) -> Result<i64, String> {
+		if self.tokens.is_empty() {
+			return Err("#if with no expression".into());
+		}
+		let value = self.conditional()?;
+		match self.peek() {
+			Some(t) => Err(format!("Unexpected '{}' in #if expression", t.text)),
+			None => Ok(value),
+		}
+	}
+
+	fn conditional(&mut self) -> Result<i64, String> {
+		let condition = self.binary(0)?;
+		if !self.peek().is_some_and(|t| t.is(Kind::Punct, "?")) {
+			return Ok(condition);
+		}
+		self.pos += 1;
+		self.dead += (condition == 0) as usize;
+		let then = self.conditional();
+		self.dead -= (condition == 0) as usize;
+		self.expect(":")?;
+		self.dead += (condition != 0) as usize;
+		let otherwise = self.conditional();
+		self.dead -= (condition != 0) as usize;
+		let (then, otherwise) = (then?, otherwise?);
+		Ok(if condition != 0 { then } else { otherwise })
+	}
+
+	fn binary(&mut self, level: usize) -> Result<i64, String> {
+		if level == BINARY_OPERATORS.len() {
+			return self.unary();
+		}
+		let mut left = self.binary(level + 1)?;
+		while let Some(op) = self.peek().filter(|t| t.kind == Kind::Punct && BINARY_OPERATORS[level].contains(&t.text.as_str())) {
+			let op = op.text.clone();
+			self.pos += 1;
+			// The right operand of && and || is not evaluated when the left decides the result
+			let skip = (op == "&&" && left == 0) || (op == "||" && left != 0);
+			self.dead += skip as usize;
+			let right = self.binary(level + 1);
+			self.dead -= skip as usize;
+			let right = right?;
+			if (op == "/" || op == "%") && right == 0 && self.dead == 0 {
+				return Err("Division by zero in #if expression".into());
+			}
+			left = match op.as_str() {
+				"||" => (left != 0 || right != 0) as i64,
+				"&&" => (left != 0 && right != 0) as i64,
+				"|" => left | right,
+				"^" => left ^ right,
+				"&" => left & right,
+				"==" => (left == right) as i64,
+				"!=" => (left != right) as i64,
+				"<" => (left < right) as i64,
+				">" => (left > right) as i64,
+				"<=" => (left <= right) as i64,
+				">=" => (left >= right) as i64,
+				"<<" => left.wrapping_shl(right as u32),
+				">>" => left.wrapping_shr(right as u32),
+				"+" => left.wrapping_add(right),
+				"-" => left.wrapping_sub(right),
+				"*" => left.wrapping_mul(right),
+				_ if right == 0 => 0,
+				"/" => left.wrapping_div(right),
+				_ => left.wrapping_rem(right),
+			};
+		}
+		Ok(left)
+	}
+
+	fn unary(&mut self) -> Result<i64, String> {
+		let Some(token) = self.peek().cloned() else {
+			return Err("Missing operand at end of #if expression".into());
+		};
+		self.pos += 1;
+		match (token.kind, token.text.as_str()) {
+			(Kind::Punct, "!") => Ok((self.unary()? == 0) as i64),
+			(Kind::Punct, "~") => Ok(!self.unary()?),
+			(Kind::Punct, "-") => Ok(self.unary()?.wrapping_neg()),
+			(Kind::Punct, "+") => self.unary(),
+			(Kind::Punct, "(") => {
+				let value = self.conditional()?;
+				self.expect(")")?;
+				Ok(value)
+			}
+			(Kind::Number, text) => {
+				let digits = text.trim_end_matches(['u', 'U', 'l', 'L']);
+				let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+					i64::from_str_radix(hex, 16)
+				} else if digits.len() > 1 && digits.starts_with('0') {
+					i64::from_str_radix(&digits[1..], 8)
+				} else {
+					digits.parse()
+				};
+				value.map_err(|_| format!("Invalid integer constant '{}' in #if expression", text))
+			}
+			(Kind::Literal, text) if text.starts_with('\'') => {
+				let inner = text.trim_matches('\'');
+				match inner.strip_prefix('\\') {
+					None if inner.chars().count() == 1 => Ok(inner.chars().next().unwrap() as i64),
+					Some("n") => Ok('\n' as i64),
+					Some("t") => Ok('\t' as i64),
+					Some("r") => Ok('\r' as i64),
+					Some("0") => Ok(0),
+					Some(c @ ("\\" | "'" | "\"")) => Ok(c.chars().next().unwrap() as i64),
+					_ => Err(format!("Invalid character constant {} in #if expression", text)),
+				}
+			}
+			_ => Err(format!("Invalid token '{}' in #if expression", token.text)),
+		}
+	}
+}
+
+/// Spells text as a C string literal
+fn string_literal(text: &str) -> String {
+	format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Formats the build time like __DATE__ ("Mmm dd yyyy") and __TIME__ ("hh:mm:ss"), in UTC
+fn build_date_and_time() -> (String, String) {
+	// SOURCE_DATE_EPOCH makes builds reproducible, as in other compilers
+	let seconds = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse().ok()).unwrap_or_else(|| {
+		SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+	});
+	let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+	// Converts days since 1970-01-01 to a civil date
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let day_of_era = z.rem_euclid(146097);
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let shifted_month = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+	let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+	let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+	const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+	(
+		format!("{} {:>2} {}", MONTHS[month as usize - 1], day, year),
+		format!("{:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60),
+	)
+}
+
+/// Finds the macro guarding a whole header, as in #ifndef X ... #endif
+/// The #endif matching the opening #ifndef must end the file, followed by nothing but comments
+fn include_guard(source: &str) -> Option<String> {
+	let mut guard = None;
+	let mut depth = 0;
+	let mut closed = false;
+	for line in source.lines() {
+		let tokens = tokenize_line(line, 0);
+		if tokens.is_empty() {
+			continue;
+		}
+		if closed {
+			return None; // code after the guard's #endif is included whatever the guard says
+		}
+		let directive = match &tokens[..] {
+			[hash, name, ..] if hash.is(Kind::Punct, "#") => Some(name.text.as_str()),
+			_ => None,
+		};
+		match (&guard, directive) {
+			(None, Some("ifndef")) if tokens.len() == 3 && tokens[2].kind == Kind::Ident => {
+				guard = Some(tokens[2].text.clone());
+				depth = 1;
+			}
+			(None, _) => return None,
+			(Some(_), Some("if" | "ifdef" | "ifndef")) => depth += 1,
+			(Some(_), Some("else" | "elif")) if depth == 1 => return None,
+			(Some(_), Some("endif")) => {
+				depth -= 1;
+				closed = depth == 0;
+			}
+			_ => {}
+		}
+	}
+	guard.filter(|_| closed)
+}
+
+/// Splits one logical line into preprocessing tokens
+fn tokenize_line(text: &str, line: usize) -> Vec<PPToken> {
+	let chars: Vec<char> = text.chars().collect();
+	let mut tokens = Vec::new();
+	let mut space = false;
+	let mut i = 0;
+
+	while i < chars.len() {
+		let c = chars[i];
+		let start = i;
+		let kind = if c == ' ' || c == '\t' || c == '\r' || c == '\x0c' {
+			space = true;
+			i += 1;
+			continue;
+		} else if c == '/' && chars.get(i + 1) == Some(&'/') {
+			break;
+		} else if c.is_ascii_alphabetic() || c == '_' {
+			while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+				i += 1;
+			}
+			Kind::Ident
+		} else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+			while i < chars.len() {
+				match chars[i] {
+					'e' | 'E' | 'p' | 'P' if matches!(chars.get(i + 1), Some('+' | '-')) => i += 2,
+					d if d.is_ascii_alphanumeric() || d == '_' || d == '.' => i += 1,
+					_ => break,
+				}
+			}
+			Kind::Number
+		} else if c == '"' || c == '\'' {
+			i += 1;
+			while i < chars.len() && chars[i] != c {
+				i += if chars[i] == '\\' { 2 } else { 1 };
+			}
+			i = (i + 1).min(chars.len());
+			Kind::Literal
+		} else {
+			let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+			i += PUNCTUATORS.iter().find(|p| rest == **p).map_or(1, |p| p.len());
+			Kind::Punct
+		};
+		tokens.push(PPToken {
+			kind,
+			text: chars[start..i].iter().collect(),
+			space,
+			line,
+			hide: Vec::new(),
+		});
+		space = false;
+	}
+	tokens
+}
+
+/// Turns macro arguments into a string literal for the # operator
+fn stringify(tokens: &[PPToken], model: &PPToken) -> PPToken {
+	let mut text = String::from("\"");
+	for (i, token) in tokens.iter().enumerate() {
+		if i > 0 && token.space {
+			text.push(' ');
+		}
+		if token.kind == Kind::Literal {
+			text.push_str(&token.text.replace('\\', "\\\\").replace('"', "\\\""));
+		} else {
+			text.push_str(&token.text);
+		}
+	}
+	text.push('"');
+	PPToken { kind: Kind::Literal, text, ..model.clone() }
+}
+
+/// Expands macros in C source text
+pub struct Preprocessor {
+	macros: HashMap<String, Macro>,
+	include_dirs: Vec<PathBuf>, // searched for <...> includes, and for "..." after the current directory
+	includes: Vec<PathBuf>,     // files currently being processed, outermost first
+	conditionals: Vec<Conditional>,
+	conditional_base: usize,    // conditionals opened before the current file
+	file: String,               // file being processed, for diagnostics
+	errors: Vec<String>,
+}
+
+impl Preprocessor {
+	/// Creates a preprocessor with only the predefined macros
+	pub fn new() -> Self {
+		let (date, time) = build_date_and_time();
+		let mut macros = HashMap::new();
+		for (name, kind, value) in [
+			("__DATE__", Kind::Literal, string_literal(&date)),
+			("__TIME__", Kind::Literal, string_literal(&time)),
+			("__C4_RUST__", Kind::Number, "1".to_string()),
+		] {
+			let token = PPToken { kind, text: value, space: false, line: 0, hide: Vec::new() };
+			macros.insert(name.to_string(), Macro { params: None, body: vec![token] });
+		}
+
+		Preprocessor {
+			macros,
+			include_dirs: Vec::new(),
+			includes: Vec::new(),
+			conditionals: Vec::new(),
+			conditional_base: 0,
+			file: String::new(),
+			errors: Vec::new(),
+		}
+	}
+
+	/// Adds a directory to search for included files, like -I
+	pub fn include_dir(&mut self, dir: &str) {
+		self.include_dirs.push(PathBuf::from(dir));
+	}
+
+	/// Defines a macro like -D, as NAME, NAME=value or NAME(params)=value
+	pub fn define(&mut self, definition: &str) {
+		let text = match definition.split_once('=') {
+			Some((name, value)) => format!("{} {}", name, value),
+			None => format!("{} 1", definition),
+		};
+		let outer = std::mem::replace(&mut self.file, "<command line>".into());
+		self.define_directive(&tokenize_line(&text, 1), 1);
+		self.file = outer;
+	}
+
+	/// Removes a macro definition like -U
+	pub fn undefine(&mut self, name: &str) {
+		self.macros.remove(name);
+	}
+
+	/// Preprocesses a whole translation unit, returning every error found
+	pub fn preprocess(mut self, source: &str, file: &str) -> Result<String, Vec<String>> {
+		self.file = file.to_string();
+		self.includes.push(PathBuf::from(file));
+		let mut output = String::new();
+		self.process_source(source, &mut output);
+
+		if self.errors.is_empty() {
+			Ok(output)
+		} else {
+			Err(self.errors)
+		}
+	}
+
+	fn error(&mut self, line: usize, message: String) {
+		self.errors.push(format!("{}:{}: {}", self.file, line, message));
+	}
+
+	/// Runs directives and expands the text lines between them
+	fn process_source(&mut self, source: &str, output: &mut String) {
+		let outer_base = std::mem::replace(&mut self.conditional_base, self.conditionals.len());
+		let mut pending = Vec::new();
+		let mut physical = source.split('\n').enumerate();
+
+		while let Some((index, first)) = physical.next() {
+			// Join lines ending in a backslash into one logical line
+			let mut text = first.to_string();
+			let mut count = 1;
+			while text.ends_with('\\') {
+				text.pop();
+				match physical.next() {
+					Some((_, next)) => {
+						text.push_str(next);
+						count += 1;
+					}
+					None => break,
+				}
+			}
+
+			let line = index + 1;
+			let tokens = tokenize_line(&text, line);
+			if tokens.first().is_some_and(|t| t.is(Kind::Punct, "#")) {
+				self.flush(&mut pending, output);
+				self.directive(&tokens[1..], line, output);
+			} else if self.active() {
+				pending.extend(tokens);
+			}
+			for _ in 0..count {
+				pending.push(PPToken { kind: Kind::Newline, text: "\n".into(), space: false, line, hide: Vec::new() });
+			}
+		}
+		// split yields one more piece than there are newlines
+		pending.pop();
+		self.flush(&mut pending, output);
+
+		// Every #if must be closed in the file that opened it
+		for conditional in self.conditionals.split_off(self.conditional_base) {
+			self.error(conditional.line, "Unterminated conditional directive".into());
+		}
+		self.conditional_base = outer_base;
+	}
+
+	/// Whether text lines are currently kept rather than skipped
+	fn active(&self) -> bool {
+		self.conditionals.last().is_none_or(|c| c.active)
+	}
+
+	/// Expands pending text tokens and writes them out
+	fn flush(&mut self, pending: &mut Vec<PPToken>, output: &mut String) {
+		let tokens = self.expand(std::mem::take(pending));
+		let mut previous: Option<&PPToken> = None;
+
+		for token in &tokens {
+			if token.kind == Kind::Newline {
+				output.push('\n');
+				previous = None;
+				continue;
+			}
+			// Keep tokens from a replacement from gluing onto their neighbours
+			let expanded = !token.hide.is_empty() || previous.is_some_and(|p| !p.hide.is_empty());
+			if previous.is_some() && (token.space || expanded) {
+				output.push(' ');
+			}
+			output.push_str(&token.text);
+			previous = Some(token);
+		}
+	}
+
+	/// Handles one directive line, given the tokens after the #
+	fn directive(&mut self, tokens: &[PPToken], line: usize, output: &mut String) {
+		let Some(name) = tokens.first() else {
+			return; // the null directive
+		};
+		match name.text.as_str() {
+			"if" | "ifdef" | "ifndef" | "elif" | "else" | "endif" => self.conditional_directive(&name.text, &tokens[1..], line),
+			// Skipped groups only track nesting
+			_ if !self.active() => {}
+			"define" => self.define_directive(&tokens[1..], line),
+			"undef" => match tokens.get(1) {
+				Some(token) if token.kind == Kind::Ident => {
+					self.macros.remove(&token.text);
+				}
+				_ => self.error(line, "Macro names must be identifiers".into()),
+			},
+			"include" => self.include_directive(&tokens[1..], line, output),
+			// Other directives are still ignored, as the lexer used to do
+			_ => {}
+		}
+	}
+
+	/// Parses the name, parameters and body of a #define
+	fn define_directive(&mut self, tokens: &[PPToken], line: usize) {
+		let name = match tokens.first() {
+			Some(token) if token.kind == Kind::Ident => token.text.clone(),
+			_ => return self.error(line, "Macro names must be identifiers".into()),
+		};
+
+		// A parameter list only counts if the ( directly follows the name
+		let mut rest = &tokens[1..];
+		let mut params = None;
+		if rest.first().is_some_and(|t| t.is(Kind::Punct, "(") && !t.space) {
+			let mut names: Vec<String> = Vec::new();
+			let mut i = 1;
+			loop {
+				match (rest.get(i), rest.get(i + 1)) {
+					(Some(t), _) if t.is(Kind::Punct, ")") && names.is_empty() => {
+						i += 1;
+						break;
+					}
+					(Some(param), Some(next)) if param.kind == Kind::Ident && (next.is(Kind::Punct, ",") || next.is(Kind::Punct, ")")) => {
+						if names.contains(&param.text) {
+							return self.error(line, format!("Duplicate macro parameter '{}'", param.text));
+						}
+						names.push(param.text.clone());
+						i += 2;
+						if next.text == ")" {
+							break;
+						}
+					}
+					_ => return self.error(line, format!("Invalid parameter list in definition of macro '{}'", name)),
+				}
+			}
+			rest = &rest[i..];
+			params = Some(names);
+		}
+
+		let mut body = rest.to_vec();
+		if let Some(first) = body.first_mut() {
+			first.space = false;
+		}
+		let definition = Macro { params, body };
+
+		if definition.body.first().is_some_and(|t| t.is(Kind::Punct, "##")) || definition.body.last().is_some_and(|t| t.is(Kind::Punct, "##")) {
+			return self.error(line, "'##' cannot appear at either end of a macro expansion".into());
+		}
+		if definition.params.is_some() {
+			for (i, token) in definition.body.iter().enumerate() {
+				let operand = definition.body.get(i + 1).and_then(|t| definition.param_index(t));
+				if token.is(Kind::Punct, "#") && operand.is_none() {
+					return self.error(line, "'#' is not followed by a macro parameter".into());
+				}
+			}
+		}
+		if self.macros.get(&name).is_some_and(|old| !old.same_as(&definition)) {
+			return self.error(line, format!("Macro '{}' redefined", name));
+		}
+		self.macros.insert(name, definition);
+	}
+
+	/// Opens, switches or closes a conditional group
+	fn conditional_directive(&mut self, directive: &str, tokens: &[PPToken], line: usize) {
+		let open = self.conditionals.len() > self.conditional_base;
+		match directive {
+			"if" | "ifdef" | "ifndef" => {
+				// Nothing inside a skipped group is evaluated
+				let active = self.active() && match directive {
+					"if" => self.condition(tokens, line),
+					_ => match tokens.first() {
+						Some(name) if name.kind == Kind::Ident => self.is_defined(&name.text) == (directive == "ifdef"),
+						_ => {
+							self.error(line, "Macro names must be identifiers".into());
+							false
+						}
+					},
+				};
+				let taken = active || !self.active();
+				self.conditionals.push(Conditional { line, active, taken, seen_else: false });
+			}
+			_ if !open => self.error(line, format!("#{} without #if", directive)),
+			"elif" | "else" => {
+				let current = *self.conditionals.last().unwrap();
+				if current.seen_else {
+					return self.error(line, format!("#{} after #else", directive));
+				}
+				let active = !current.taken && (directive == "else" || self.condition(tokens, line));
+				*self.conditionals.last_mut().unwrap() = Conditional {
+					line: current.line,
+					active,
+					taken: current.taken || active,
+					seen_else: directive == "else",
+				};
+			}
+			_ => {
+				self.conditionals.pop();
+			}
+		}
+	}
+
+	/// Evaluates the controlling expression of #if or #elif
+	fn condition(&mut self, tokens: &[PPToken], line: usize) -> bool {
+		// defined X and defined(X) are replaced before macros are expanded
+		let mut replaced = Vec::new();
+		let mut i = 0;
+		while i < tokens.len() {
+			if tokens[i].kind != Kind::Ident || tokens[i].text != "defined" {
+				replaced.push(tokens[i].clone());
+				i += 1;
+				continue;
+			}
+			let parens = tokens.get(i + 1).is_some_and(|t| t.is(Kind::Punct, "("));
+			let name = tokens.get(i + 1 + parens as usize).filter(|t| t.kind == Kind::Ident);
+			let closed = !parens || tokens.get(i + 3).is_some_and(|t| t.is(Kind::Punct, ")"));
+			let Some(name) = name.filter(|_| closed) else {
+				self.error(line, "Operator 'defined' requires an identifier".into());
+				return false;
+			};
+			let value = self.is_defined(&name.text) as i64;
+			replaced.push(PPToken { kind: Kind::Number, text: value.to_string(), ..tokens[i].clone() });
+			i += if parens { 4 } else { 2 };
+		}
+
+		// Identifiers left after expansion count as 0, except __LINE__,
+		// which the lexer fills in for text lines but only this directive can for #if
+		let mut expanded = self.expand(replaced);
+		for token in expanded.iter_mut().filter(|t| t.kind == Kind::Ident) {
+			token.kind = Kind::Number;
+			token.text = if token.text == "__LINE__" { line.to_string() } else { "0".into() };
+		}
+		match (Evaluator { tokens: &expanded, pos: 0, dead: 0 }).evaluate() {
+			Ok(value) => value != 0,
+			Err(message) => {
+				self.error(line, message);
+				false
+			}
+		}
+	}
+
+	/// Finds the file named by an #include and splices in its preprocessed text
+	fn include_directive(&mut self, tokens: &[PPToken], line: usize, output: &mut String) {
+		// Anything other than "file" or <file> is macro expanded first
+		let mut tokens = tokens.to_vec();
+		if !tokens.first().is_some_and(|t| t.kind == Kind::Literal || t.is(Kind::Punct, "<")) {
+			tokens = self.expand(tokens);
+		}
+
+		let (name, angled) = match tokens.first() {
+			Some(t) if t.kind == Kind::Literal && t.text.starts_with('"') && t.text.len() >= 2 => {
+				(t.text[1..t.text.len() - 1].to_string(), false)
+			}
+			Some(t) if t.is(Kind::Punct, "<") && tokens.iter().any(|t| t.is(Kind::Punct, ">")) => {
+				let mut name = String::new();
+				for token in tokens[1..].iter().take_while(|t| !t.is(Kind::Punct, ">")) {
+					if token.space && !name.is_empty() {
+						name.push(' ');
+					}
+					name.push_str(&token.text);
+				}
+				(name, true)
+			}
+			_ => return self.error(line, "#include expects \"FILENAME\" or <FILENAME>".into()),
+		};
+
+		// "file" is looked up next to the including file before the search path
+		let current = self.includes.last().unwrap().clone();
+		let local = current.parent().map(|dir| dir.join(&name));
+		let candidates = local.filter(|_| !angled).into_iter().chain(self.include_dirs.iter().map(|dir| dir.join(&name)));
+		let found = candidates.into_iter().find(|path| path.is_file()).map(|path| {
+			let source = fs::read_to_string(&path);
+			(path, source)
+		});
+		let builtin = || BUILTIN_HEADERS.iter().find(|(header, _)| *header == name).map(|(header, source)| {
+			(PathBuf::from(format!("<{}>", header)), Ok(source.to_string()))
+		});
+		let (path, source) = match found.or_else(builtin) {
+			Some((path, Ok(source))) => (path, source),
+			Some((path, Err(err))) => return self.error(line, format!("Could not read '{}': {}", path.display(), err)),
+			None => return self.error(line, format!("Include file '{}' not found", name)),
+		};
+
+		// A header whose guard is already defined would expand to nothing
+		if include_guard(&source).is_some_and(|guard| self.macros.contains_key(&guard)) {
+			return;
+		}
+
+		if self.includes.len() >= MAX_INCLUDE_DEPTH {
+			return self.error(line, format!("#include nested more than {} levels deep", MAX_INCLUDE_DEPTH));
+		}
+		let same_file = |a: &Path, b: &Path| match (fs::canonicalize(a), fs::canonicalize(b)) {
+			(Ok(a), Ok(b)) => a == b,
+			_ => a == b,
+		};
+		if let Some(start) = self.includes.iter().position(|open| same_file(open, &path)) {
+			let chain: Vec<String> = self.includes[start..].iter().chain([&path]).map(|p| p.display().to_string()).collect();
+			return self.error(line, format!("Include cycle: {}", chain.join(" -> ")));
+		}
+
+		// Line markers let the lexer follow the included file and then return
+		let outer = std::mem::replace(&mut self.file, path.display().to_string());
+		output.push_str(&format!("#line 1 \"{}\"\n", self.file));
+		self.includes.push(path);
+		self.process_source(&source, output);
+		self.includes.pop();
+		self.file = outer;
+		output.push_str(&format!("\n#line {} \"{}\"", line + 1, self.file));
+	}
+
+	/// Whether a name is a macro, for #ifdef and defined()
+	fn is_defined(&self, name: &str) -> bool {
+		self.macros.contains_key(name) || DYNAMIC_MACROS.contains(&name)
+	}
+
+	/// Returns the macro a token invokes, unless the token is hidden from it
+	fn macro_for(&self, token: &PPToken) -> Option<Macro> {
+		if token.kind != Kind::Ident || token.hide.contains(&token.text) {
+			return None;
+		}
+		if token.text == "__FILE__" {
+			let file = PPToken { kind: Kind::Literal, text: string_literal(&self.file), ..token.clone() };
+			return Some(Macro { params: None, body: vec![file] });
+		}
+		self.macros.get(&token.text).cloned()
+	}
+
+	/// Expands every macro invocation in a token list, rescanning replacements
+	fn expand(&mut self, tokens: Vec<PPToken>) -> Vec<PPToken> {
+		let mut input: VecDeque<PPToken> = tokens.into();
+		let mut output = Vec::new();
+
+		while let Some(token) = input.pop_front() {
+			let Some(definition) = self.macro_for(&token) else {
+				output.push(token);
+				continue;
+			};
+
+			let mut newlines = 0;
+			let replacement = match &definition.params {
+				None => self.substitute(&definition, &[], &token),
+				Some(params) => {
+					// A function-like macro name without arguments is left alone
+					let open = input.iter().position(|t| t.kind != Kind::Newline);
+					if !open.is_some_and(|i| input[i].is(Kind::Punct, "(")) {
+						output.push(token);
+						continue;
+					}
+					let Some(args) = self.collect_args(&mut input, &token, &mut newlines) else {
+						continue;
+					};
+					if args.len() != params.len() && !(params.is_empty() && args.len() == 1 && args[0].is_empty()) {
+						self.error(token.line, format!(
+							"Macro '{}' expects {} argument(s), but {} were given",
+							token.text, params.len(), args.len()
+						));
+						continue;
+					}
+					self.substitute(&definition, &args, &token)
+				}
+			};
+
+			// Newlines inside the arguments come back after the replacement to keep line numbers
+			for _ in 0..newlines {
+				input.push_front(PPToken { kind: Kind::Newline, text: "\n".into(), space: false, line: token.line, hide: Vec::new() });
+			}
+			for replaced in replacement.into_iter().rev() {
+				input.push_front(replaced);
+			}
+		}
+		output
+	}
+
+	/// Reads the parenthesised, comma separated arguments of a macro call
+	fn collect_args(&mut self, input: &mut VecDeque<PPToken>, name: &PPToken, newlines: &mut usize) -> Option<Vec<Vec<PPToken>>> {
+		let mut args = vec![Vec::new()];
+		let mut depth = 0;
+		let mut space = false;
+
+		while let Some(mut token) = input.pop_front() {
+			if token.kind == Kind::Newline {
+				*newlines += 1;
+				space = true;
+				continue;
+			}
+			token.space |= space;
+			space = false;
+			match token.text.as_str() {
+				"(" if token.kind == Kind::Punct => {
+					depth += 1;
+					if depth == 1 {
+						continue;
+					}
+				}
+				")" if token.kind == Kind::Punct => {
+					depth -= 1;
+					if depth == 0 {
+						return Some(args);
+					}
+				}
+				"," if token.kind == Kind::Punct && depth == 1 => {
+					args.push(Vec::new());
+					continue;
+				}
+				_ => {}
+			}
+			args.last_mut().unwrap().push(token);
+		}
+		self.error(name.line, format!("Unterminated argument list invoking macro '{}'", name.text));
+		None
+	}
+
+	/// Builds the replacement list of one invocation
+	fn substitute(&mut self, definition: &Macro, args: &[Vec<PPToken>], invocation: &PPToken) -> Vec<PPToken> {
+		let body = &definition.body;
+		let mut result: Vec<PPToken> = Vec::new();
+		let mut i = 0;
+
+		while i < body.len() {
+			let token = &body[i];
+			let param = body.get(i + 1).and_then(|t| definition.param_index(t));
+
+			if token.is(Kind::Punct, "#") && definition.params.is_some() {
+				let mut string = stringify(&args[param.unwrap()], token);
+				string.space = token.space;
+				result.push(string);
+				i += 2;
+			} else if token.is(Kind::Punct, "##") {
+				let left = result.pop().unwrap();
+				let mut right = match param {
+					Some(index) => args[index].clone(),
+					None => vec![body[i + 1].clone()],
+				};
+				if right.is_empty() {
+					right.push(PPToken { kind: Kind::Placemarker, text: String::new(), ..left.clone() });
+				}
+				let first = right.remove(0);
+				let pasted = self.paste(left, first);
+				result.extend(pasted);
+				result.extend(right);
+				i += 2;
+			} else if let Some(index) = definition.param_index(token) {
+				// Operands of ## are used as written, other arguments are expanded first
+				let next_is_paste = body.get(i + 1).is_some_and(|t| t.is(Kind::Punct, "##"));
+				let mut arg = if next_is_paste { args[index].clone() } else { self.expand(args[index].clone()) };
+				match arg.first_mut() {
+					Some(first) => first.space = token.space,
+					None if next_is_paste => arg.push(PPToken { kind: Kind::Placemarker, text: String::new(), ..token.clone() }),
+					None => {}
+				}
+				result.extend(arg);
+				i += 1;
+			} else {
+				result.push(token.clone());
+				i += 1;
+			}
+		}
+
+		result.retain(|t| t.kind != Kind::Placemarker);
+		if let Some(first) = result.first_mut() {
+			first.space = invocation.space;
+		}
+		for token in &mut result {
+			token.line = invocation.line;
+			token.hide.extend(invocation.hide.iter().cloned());
+			token.hide.push(invocation.text.clone());
+		}
+		result
+	}
+
+	/// Joins two tokens with ##, which must form a single token
+	fn paste(&mut self, left: PPToken, right: PPToken) -> Vec<PPToken> {
+		if left.kind == Kind::Placemarker {
+			return vec![right];
+		}
+		if right.kind == Kind::Placemarker {
+			return vec![left];
+		}
+		let text = format!("{}{}", left.text, right.text);
+		let mut tokens = tokenize_line(&text, left.line);
+		if tokens.len() != 1 {
+			self.error(left.line, format!(
+				"Pasting \"{}\" and \"{}\" does not give a valid preprocessing token",
+				left.text, right.text
+			));
+			return vec![left, right];
+		}
+		let mut token = tokens.remove(0);
+		token.space = left.space;
+		vec![token]
+	}
+}
+
+impl Default for Preprocessor {
+	fn default() -> Self {
+		Self::new()
+	}
+}
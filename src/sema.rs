@@ -0,0 +1,313 @@
+// Semantic analysis file
+
+/*
+Runs between parsing and code generation so that name errors are
+reported before any code runs.
+Checks:
+Undeclared variables,
+Redeclarations in the same scope,
+Calls to unknown functions,
+Wrong number of arguments,
+Duplicate or misplaced case labels and break statements,
+Undefined or duplicate goto labels
+*/
+
+use crate::parser::ASTNode;
+use crate::vm::builtins;
+use std::collections::{HashMap, HashSet};
+
+/// What a name refers to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Symbol {
+	Variable,
+	Function(Option<usize>), // number of parameters, None if variadic (at least one argument)
+}
+
+/// Walks the AST with a stack of scopes and collects semantic errors
+pub struct Analyzer {
+	scopes: Vec<HashMap<String, Symbol>>, // innermost scope last, globals first
+	current_function: Option<String>,
+	switches: Vec<HashSet<Option<i64>>>, // case values seen by each enclosing switch, None for default
+	breakable: usize,                    // number of enclosing loops and switches
+	labels: HashSet<String>,             // goto labels of the current function
+	defined: HashSet<String>,            // functions with a body, including the builtins
+	errors: Vec<String>,
+}
+
+impl Analyzer {
+	/// Creates an analyzer whose global scope holds the VM builtins
+	pub fn new() -> Self {
+		let mut globals = HashMap::new();
+		let mut defined = HashSet::new();
+		// Functions provided by the VM rather than defined in the source
+		for builtin in builtins() {
+			let arity = if builtin.variadic { None } else { Some(builtin.params.len()) };
+			globals.insert(builtin.name.to_string(), Symbol::Function(arity));
+			defined.insert(builtin.name.to_string());
+		}
+		Analyzer {
+			scopes: vec![globals],
+			current_function: None,
+			switches: Vec::new(),
+			breakable: 0,
+			labels: HashSet::new(),
+			defined,
+			errors: Vec::new(),
+		}
+	}
+
+	/// Checks a whole program, returning every error found
+	pub fn analyze(mut self, program: &[ASTNode]) -> Result<(), Vec<String>> {
+		// Functions may be called before their definition appears
+		for node in program {
+			match node {
+				ASTNode::FuncDef { name, params, .. } => self.declare_function(name, Some(params.len()), true),
+				ASTNode::FuncDecl { name, params, variadic, .. } => {
+					let arity = if *variadic { None } else { Some(params.len()) };
+					self.declare_function(name, arity, false);
+				}
+				_ => {}
+			}
+		}
+
+		for node in program {
+			match node {
+				ASTNode::FuncDef { name, params, body, .. } => {
+					self.current_function = Some(name.clone());
+					self.scopes.push(HashMap::new());
+					for (_, param) in params {
+						self.declare(param, Symbol::Variable);
+					}
+					// Labels are visible throughout the function, even before their definition
+					self.labels.clear();
+					self.collect_labels(body);
+					for stmt in body {
+						self.check(stmt);
+					}
+					self.scopes.pop();
+					self.current_function = None;
+				}
+				_ => self.check(node),
+			}
+		}
+
+		if self.errors.is_empty() {
+			Ok(())
+		} else {
+			Err(self.errors)
+		}
+	}
+
+	/// Records an error, naming the function it occurred in
+	fn error(&mut self, message: String) {
+		match &self.current_function {
+			Some(function) => self.errors.push(format!("{} (in function '{}')", message, function)),
+			None => self.errors.push(message),
+		}
+	}
+
+	/// Adds a name to the innermost scope, reporting redeclarations
+	fn declare(&mut self, name: &str, symbol: Symbol) {
+		let scope = self.scopes.last_mut().unwrap();
+		if scope.contains_key(name) {
+			self.error(format!("Redeclaration of '{}'", name));
+		} else {
+			scope.insert(name.to_string(), symbol);
+		}
+	}
+
+	/// Declares a function, allowing any number of prototypes that agree with it
+	fn declare_function(&mut self, name: &str, arity: Option<usize>, definition: bool) {
+		match self.scopes[0].get(name) {
+			Some(Symbol::Function(existing)) if *existing != arity => {
+				self.error(format!("Conflicting types for '{}'", name));
+			}
+			Some(Symbol::Function(_)) if !definition || self.defined.insert(name.to_string()) => {}
+			Some(_) => self.error(format!("Redeclaration of '{}'", name)),
+			None => {
+				if definition {
+					self.defined.insert(name.to_string());
+				}
+				self.scopes[0].insert(name.to_string(), Symbol::Function(arity));
+			}
+		}
+	}
+
+	/// Finds the innermost declaration of a name
+	fn lookup(&self, name: &str) -> Option<Symbol> {
+		self.scopes.iter().rev().find_map(|scope| scope.get(name).copied())
+	}
+
+	/// Reports a use of a name that does not refer to a variable
+	fn check_variable(&mut self, name: &str) {
+		match self.lookup(name) {
+			Some(Symbol::Variable) => {}
+			Some(Symbol::Function(_)) => self.error(format!("Function '{}' used as a variable", name)),
+			None => self.error(format!("Undeclared variable '{}'", name)),
+		}
+	}
+
+	/// Checks statements in a nested scope, e.g. an if or while body
+	fn check_scoped(&mut self, nodes: &[ASTNode]) {
+		self.scopes.push(HashMap::new());
+		for node in nodes {
+			self.check(node);
+		}
+		self.scopes.pop();
+	}
+
+	/// Records every goto label in a function body, reporting duplicates
+	fn collect_labels(&mut self, nodes: &[ASTNode]) {
+		for node in nodes {
+			match node {
+				ASTNode::Label(name) if !self.labels.insert(name.clone()) => {
+					self.error(format!("Duplicate label '{}'", name));
+				}
+				ASTNode::If { then_branch, else_branch, .. } => {
+					self.collect_labels(std::slice::from_ref(then_branch));
+					if let Some(else_branch) = else_branch {
+						self.collect_labels(std::slice::from_ref(else_branch));
+					}
+				}
+				ASTNode::WhileLoop { body, .. } | ASTNode::ForLoop { body, .. } | ASTNode::Switch { body, .. }
+				| ASTNode::Block(body) | ASTNode::FuncCall { args: body, .. } => self.collect_labels(body),
+				_ => {}
+			}
+		}
+	}
+
+	/// Records a case label (None for default) in the innermost switch
+	fn check_label(&mut self, label: Option<i64>) {
+		let duplicate = match self.switches.last_mut() {
+			Some(labels) => !labels.insert(label),
+			None => {
+				match label {
+					Some(_) => self.error("Case label not within a switch statement".to_string()),
+					None => self.error("'default' label not within a switch statement".to_string()),
+				}
+				return;
+			}
+		};
+		if duplicate {
+			match label {
+				Some(value) => self.error(format!("Duplicate case value '{}'", value)),
+				None => self.error("Multiple default labels in one switch".to_string()),
+			}
+		}
+	}
+
+	/// Checks a single statement or expression
+	fn check(&mut self, node: &ASTNode) {
+		match node {
+			ASTNode::Num(_) | ASTNode::FNum(_) | ASTNode::Str(_) | ASTNode::SizeofType(_) => {}
+			ASTNode::SizeofExpr(expr) => self.check(expr),
+			ASTNode::Id(name) => self.check_variable(name),
+			ASTNode::Return(expr) | ASTNode::UnaryOp { expr, .. } | ASTNode::PostfixOp { expr, .. }
+			| ASTNode::Cast { expr, .. } => self.check(expr),
+			ASTNode::Member { expr, .. } => self.check(expr), // member names are checked by the type checker
+			ASTNode::Block(stmts) | ASTNode::InitList(stmts) => {
+				for stmt in stmts {
+					self.check(stmt);
+				}
+			}
+			ASTNode::BinaryOp { left, right, .. }
+			| ASTNode::Index { array: left, index: right }
+			| ASTNode::MemAssign { target: left, value: right } => {
+				self.check(left);
+				self.check(right);
+			}
+			ASTNode::FuncCall { name, args } if name == "__block" => self.check_scoped(args),
+			ASTNode::FuncCall { name, args } => {
+				match self.lookup(name) {
+					Some(Symbol::Function(Some(count))) if count != args.len() => {
+						self.error(format!(
+							"Function '{}' expects {} argument(s), but {} were given",
+							name, count, args.len()
+						));
+					}
+					Some(Symbol::Function(None)) if args.is_empty() => {
+						self.error(format!("Function '{}' expects at least 1 argument", name));
+					}
+					Some(Symbol::Function(_)) if !self.defined.contains(name) => {
+						self.error(format!("Call to undefined function '{}'", name));
+					}
+					Some(Symbol::Function(_)) => {}
+					Some(Symbol::Variable) => self.error(format!("'{}' is not a function", name)),
+					None => self.error(format!("Call to undefined function '{}'", name)),
+				}
+				for arg in args {
+					self.check(arg);
+				}
+			}
+			ASTNode::FuncDef { name, .. } => {
+				self.error(format!("Function '{}' defined inside another function", name));
+			}
+			ASTNode::Assign { name, value } => {
+				self.check(value);
+				self.check_variable(name);
+			}
+			ASTNode::Cond { cond, then_branch, else_branch } => {
+				self.check(cond);
+				self.check(then_branch);
+				self.check(else_branch);
+			}
+			ASTNode::If { cond, then_branch, else_branch } => {
+				self.check(cond);
+				self.check_scoped(std::slice::from_ref(then_branch));
+				if let Some(else_branch) = else_branch {
+					self.check_scoped(std::slice::from_ref(else_branch));
+				}
+			}
+			ASTNode::WhileLoop { condition, body } => {
+				self.check(condition);
+				self.breakable += 1;
+				self.check_scoped(body);
+				self.breakable -= 1;
+			}
+			ASTNode::ForLoop { init, condition, step, body } => {
+				// A declaration in the header is scoped to the loop
+				self.scopes.push(HashMap::new());
+				for clause in [init, condition, step].into_iter().flatten() {
+					self.check(clause);
+				}
+				self.breakable += 1;
+				self.check_scoped(body);
+				self.breakable -= 1;
+				self.scopes.pop();
+			}
+			ASTNode::Switch { expr, body } => {
+				self.check(expr);
+				self.switches.push(HashSet::new());
+				self.breakable += 1;
+				self.check_scoped(body);
+				self.breakable -= 1;
+				self.switches.pop();
+			}
+			ASTNode::Case(value) => self.check_label(Some(*value)),
+			ASTNode::Default => self.check_label(None),
+			ASTNode::Label(_) | ASTNode::FuncDecl { .. } => {}
+			ASTNode::Goto(name) => {
+				if !self.labels.contains(name) {
+					self.error(format!("Label '{}' used but not defined", name));
+				}
+			}
+			ASTNode::Break => {
+				if self.breakable == 0 {
+					self.error("Break statement not within loop or switch".to_string());
+				}
+			}
+			ASTNode::Decl { name, .. } => self.declare(name, Symbol::Variable),
+			ASTNode::DeclAssign { name, value, .. } => {
+				// The variable is in scope in its own initializer, as in `p = malloc(sizeof(*p))`
+				self.declare(name, Symbol::Variable);
+				self.check(value);
+			}
+		}
+	}
+}
+
+impl Default for Analyzer {
+	fn default() -> Self {
+		Self::new()
+	}
+}
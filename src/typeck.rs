@@ -0,0 +1,688 @@
+// Type checker file
+
+/*
+Runs after semantic analysis, once every name is known to be declared.
+Computes the type of each expression and rewrites the AST so the code
+generator does not need to reason about conversions:
+Implicit conversions become Cast nodes (e.g. int to char on assignment),
+Pointer arithmetic is scaled by the size of the pointed-to type,
+Incompatible operands are reported as errors or warnings
+*/
+
+use crate::lexer::{literal_bytes, Token};
+use crate::parser::ASTNode;
+use crate::types::Type;
+use crate::vm::builtins;
+use std::collections::HashMap;
+
+/// Computes expression types and inserts conversions into the AST
+pub struct TypeChecker {
+	scopes: Vec<HashMap<String, Type>>, // innermost scope last, globals first
+	return_type: Type,
+	current_function: Option<String>,
+	pub errors: Vec<String>,
+	pub warnings: Vec<String>,
+}
+
+/// Whether values of this type are numbers (integers or floating point)
+fn is_arithmetic(ty: &Type) -> bool {
+	matches!(ty, Type::Int | Type::Char | Type::Float | Type::Double)
+}
+
+/// Whether values of this type are integers
+fn is_integer(ty: &Type) -> bool {
+	matches!(ty, Type::Int | Type::Char)
+}
+
+/// Whether values of this type can be tested for truth
+fn is_scalar(ty: &Type) -> bool {
+	is_arithmetic(ty) || ty.target().is_some()
+}
+
+/// Arrays used as values decay to a pointer to their first element
+fn decay(ty: Type) -> Type {
+	match ty {
+		Type::Array(elem, _) => Type::Pointer(elem),
+		other => other,
+	}
+}
+
+/// The type both operands of an arithmetic operator are converted to
+fn usual_arithmetic(left: &Type, right: &Type) -> Type {
+	if *left == Type::Double || *right == Type::Double {
+		Type::Double
+	} else if *left == Type::Float || *right == Type::Float {
+		Type::Float
+	} else {
+		Type::Int // char operands are promoted to int
+	}
+}
+
+/// Whether an expression is the null pointer constant `0`
+fn is_null(node: &ASTNode) -> bool {
+	matches!(node, ASTNode::Num(0))
+}
+
+/// Whether an expression designates an object in memory or a variable
+fn is_lvalue(node: &ASTNode) -> bool {
+	matches!(node, ASTNode::Id(_) | ASTNode::Index { .. } | ASTNode::Member { .. } | ASTNode::UnaryOp { op: Token::Mul, .. })
+}
+
+/// Spells an operator token the way it appears in C source
+fn op_symbol(op: &Token) -> &'static str {
+	match op {
+		Token::Add => "+", Token::Sub => "-", Token::Mul => "*", Token::Div => "/", Token::Mod => "%",
+		Token::Eq => "==", Token::Ne => "!=", Token::Lt => "<", Token::Gt => ">", Token::Le => "<=", Token::Ge => ">=",
+		Token::Lan => "&&", Token::Lor => "||", Token::And => "&", Token::Or => "|", Token::Xor => "^",
+		Token::Shl => "<<", Token::Shr => ">>",
+		_ => "?",
+	}
+}
+
+impl TypeChecker {
+	/// Creates a checker with an empty global scope
+	pub fn new() -> Self {
+		let mut globals = HashMap::new();
+		for builtin in builtins() {
+			// Variadic builtins list no parameters, so their arguments are not checked
+			let params = if builtin.variadic { Vec::new() } else { builtin.params };
+			globals.insert(builtin.name.to_string(), Type::Function {
+				return_type: Box::new(builtin.return_type),
+				params,
+			});
+		}
+		TypeChecker {
+			scopes: vec![globals],
+			return_type: Type::Int,
+			current_function: None,
+			errors: Vec::new(),
+			warnings: Vec::new(),
+		}
+	}
+
+	/// Checks a whole program and returns it with conversions made explicit
+	pub fn check_program(&mut self, program: Vec<ASTNode>) -> Vec<ASTNode> {
+		for node in &program {
+			let (return_type, name, params) = match node {
+				ASTNode::FuncDef { return_type, name, params, .. } => (return_type, name, params.as_slice()),
+				// Like the builtins, variadic prototypes leave their arguments unchecked
+				ASTNode::FuncDecl { return_type, name, params, variadic } => (return_type, name, if *variadic { &[][..] } else { params }),
+				_ => continue,
+			};
+			let ty = Type::Function {
+				return_type: Box::new(return_type.clone()),
+				params: params.iter().map(|(ty, _)| ty.clone()).collect(),
+			};
+			if self.scopes[0].get(name).is_some_and(|declared| *declared != ty) {
+				self.error(format!("conflicting types for '{}'", name));
+			}
+			self.scopes[0].insert(name.clone(), ty);
+		}
+
+		program.into_iter().map(|node| match node {
+			ASTNode::FuncDef { return_type, name, params, body } => {
+				self.current_function = Some(name.clone());
+				self.return_type = return_type.clone();
+				self.scopes.push(params.iter().map(|(ty, param)| (param.clone(), ty.clone())).collect());
+				let body = body.into_iter().map(|stmt| self.stmt(stmt)).collect();
+				self.scopes.pop();
+				self.current_function = None;
+				ASTNode::FuncDef { return_type, name, params, body }
+			}
+			other => self.stmt(other),
+		}).collect()
+	}
+
+	/// Reports an error if a struct type has no body yet
+	fn complete(&mut self, ty: &Type, message: &str) {
+		let mut base = ty;
+		while let Type::Array(elem, _) = base {
+			base = elem;
+		}
+		if matches!(base, Type::Struct(aggregate) if !aggregate.is_complete()) {
+			self.error(message.to_string());
+		}
+	}
+
+	/// Adds the function name to a diagnostic
+	fn in_function(&self, message: String) -> String {
+		match &self.current_function {
+			Some(function) => format!("{} (in function '{}')", message, function),
+			None => message,
+		}
+	}
+
+	fn error(&mut self, message: String) {
+		let message = self.in_function(message);
+		self.errors.push(message);
+	}
+
+	fn warning(&mut self, message: String) {
+		let message = self.in_function(message);
+		self.warnings.push(message);
+	}
+
+	/// Looks up the type of a name, treating unknown names as int
+	fn lookup(&self, name: &str) -> Type {
+		self.scopes.iter().rev().find_map(|scope| scope.get(name).cloned()).unwrap_or(Type::Int)
+	}
+
+	fn declare(&mut self, name: &str, ty: Type) {
+		self.scopes.last_mut().unwrap().insert(name.to_string(), ty);
+	}
+
+	/// Wraps an expression in a conversion if its type differs from the target
+	fn cast(node: ASTNode, from: &Type, to: &Type) -> ASTNode {
+		if from == to {
+			node
+		} else {
+			ASTNode::Cast { typename: to.clone(), expr: Box::new(node) }
+		}
+	}
+
+	/// Converts a value as if by assignment, e.g. for initialization, arguments and return
+	/// `context` names the operation in diagnostics, like "assignment"
+	fn convert(&mut self, node: ASTNode, from: Type, to: &Type, context: &str) -> ASTNode {
+		let from = decay(from);
+		if from == Type::Void {
+			self.error("void value not ignored as it ought to be".to_string());
+			return node;
+		}
+		if matches!(from, Type::Struct(_)) || matches!(to, Type::Struct(_)) {
+			// Structs are only handled through pointers
+			if from == *to {
+				self.error(format!("{} of '{}' by value is not supported", context, to));
+			} else {
+				self.error(format!("incompatible types in {} ('{}' from '{}')", context, to, from));
+			}
+			return node;
+		}
+		if is_arithmetic(&from) && is_arithmetic(to) {
+			return Self::cast(node, &from, to);
+		}
+		match (from.target(), to.target()) {
+			(Some(from_target), Some(to_target)) => {
+				let compatible = from_target == to_target
+					|| *from_target == Type::Void
+					|| *to_target == Type::Void;
+				if !compatible {
+					self.warning(format!("incompatible pointer types in {} ('{}' from '{}')", context, to, from));
+				}
+			}
+			(None, Some(_)) if from.is_floating() => {
+				self.error(format!("incompatible types in {} ('{}' from '{}')", context, to, from));
+			}
+			(None, Some(_)) if !is_null(&node) => {
+				self.warning(format!("{} makes pointer from integer without a cast", context));
+			}
+			(Some(_), None) if is_integer(to) => {
+				self.warning(format!("{} makes integer from pointer without a cast", context));
+			}
+			(Some(_), None) => {
+				self.error(format!("incompatible types in {} ('{}' from '{}')", context, to, from));
+			}
+			_ => {}
+		}
+		node
+	}
+
+	/// Checks a statement in its own scope, e.g. an if or while body
+	fn scoped_stmts(&mut self, stmts: Vec<ASTNode>) -> Vec<ASTNode> {
+		self.scopes.push(HashMap::new());
+		let stmts = stmts.into_iter().map(|stmt| self.stmt(stmt)).collect();
+		self.scopes.pop();
+		stmts
+	}
+
+	/// Checks a condition, which must be a scalar value
+	fn condition(&mut self, node: ASTNode) -> ASTNode {
+		let (node, ty) = self.expr(node);
+		if !is_scalar(&ty) {
+			self.error(format!("used '{}' where a scalar is required", ty));
+		}
+		Self::truth(node, &ty)
+	}
+
+	/// Floating values are true when they compare unequal to zero, not when any bit is set
+	fn truth(node: ASTNode, ty: &Type) -> ASTNode {
+		if !ty.is_floating() {
+			return node;
+		}
+		ASTNode::BinaryOp {
+			op: Token::Ne,
+			left: Box::new(Self::cast(node, ty, &Type::Double)),
+			right: Box::new(ASTNode::FNum(0.0)),
+		}
+	}
+
+	/// Checks a statement
+	fn stmt(&mut self, node: ASTNode) -> ASTNode {
+		match node {
+			ASTNode::Block(stmts) => ASTNode::Block(stmts.into_iter().map(|stmt| self.stmt(stmt)).collect()),
+			ASTNode::FuncCall { name, args } if name == "__block" => {
+				ASTNode::FuncCall { name, args: self.scoped_stmts(args) }
+			}
+			ASTNode::If { cond, then_branch, else_branch } => {
+				let cond = self.condition(*cond);
+				let then_branch = self.scoped_stmts(vec![*then_branch]).remove(0);
+				let else_branch = else_branch.map(|branch| Box::new(self.scoped_stmts(vec![*branch]).remove(0)));
+				ASTNode::If { cond: Box::new(cond), then_branch: Box::new(then_branch), else_branch }
+			}
+			ASTNode::WhileLoop { condition, body } => {
+				let condition = self.condition(*condition);
+				ASTNode::WhileLoop { condition: Box::new(condition), body: self.scoped_stmts(body) }
+			}
+			ASTNode::ForLoop { init, condition, step, body } => {
+				self.scopes.push(HashMap::new());
+				let init = init.map(|init| Box::new(self.stmt(*init)));
+				let condition = condition.map(|condition| Box::new(self.condition(*condition)));
+				let step = step.map(|step| Box::new(self.stmt(*step)));
+				let body = self.scoped_stmts(body);
+				self.scopes.pop();
+				ASTNode::ForLoop { init, condition, step, body }
+			}
+			// Both operands of a discarded comma expression may be void
+			ASTNode::BinaryOp { op: Token::Comma, left, right } => ASTNode::BinaryOp {
+				op: Token::Comma,
+				left: Box::new(self.stmt(*left)),
+				right: Box::new(self.stmt(*right)),
+			},
+			ASTNode::Switch { expr, body } => {
+				let (expr, ty) = self.expr(*expr);
+				if !is_integer(&ty) {
+					self.error("switch quantity not an integer".to_string());
+				}
+				ASTNode::Switch { expr: Box::new(expr), body: self.scoped_stmts(body) }
+			}
+			ASTNode::Case(_) | ASTNode::Default | ASTNode::Break | ASTNode::Label(_) | ASTNode::Goto(_)
+			| ASTNode::FuncDecl { .. } => node,
+			ASTNode::Return(expr) => {
+				let (expr, ty) = self.expr(*expr);
+				let return_type = self.return_type.clone();
+				let expr = if return_type == Type::Void {
+					expr // `return;` is parsed as returning 0
+				} else {
+					self.convert(expr, ty, &return_type, "return")
+				};
+				ASTNode::Return(Box::new(expr))
+			}
+			ASTNode::Decl { typename, name } => {
+				if typename == Type::Void {
+					self.error(format!("variable '{}' declared void", name));
+				}
+				self.complete(&typename, &format!("storage size of '{}' isn't known", name));
+				self.declare(&name, typename.clone());
+				ASTNode::Decl { typename, name }
+			}
+			ASTNode::DeclAssign { typename, name, value } => {
+				self.complete(&typename, &format!("storage size of '{}' isn't known", name));
+				self.declare(&name, typename.clone());
+				let value = self.initializer(*value, &typename);
+				ASTNode::DeclAssign { typename, name, value: Box::new(value) }
+			}
+			// Calls whose value is discarded may return void
+			ASTNode::FuncCall { name, args } => self.call(name, args).0,
+			other => self.expr(other).0,
+		}
+	}
+
+	/// Checks an initializer against the declared type
+	fn initializer(&mut self, node: ASTNode, ty: &Type) -> ASTNode {
+		match (ty, node) {
+			(Type::Array(elem, size), ASTNode::InitList(values)) => {
+				if values.len() > *size {
+					self.error("excess elements in array initializer".to_string());
+				}
+				ASTNode::InitList(values.into_iter().map(|value| self.initializer(value, elem)).collect())
+			}
+			(Type::Array(elem, size), ASTNode::Str(s)) if **elem == Type::Char => {
+				// The terminating NUL may be dropped, but no other character
+				if literal_bytes(&s).len() > *size {
+					self.error("initializer-string for array of 'char' is too long".to_string());
+				}
+				ASTNode::Str(s)
+			}
+			(Type::Array(..), _) => {
+				self.error("invalid initializer for array".to_string());
+				ASTNode::InitList(Vec::new())
+			}
+			(Type::Struct(aggregate), ASTNode::InitList(values)) => {
+				let members = aggregate.members();
+				// A union initializer sets its first member only
+				let limit = if aggregate.is_union { members.len().min(1) } else { members.len() };
+				if values.len() > limit {
+					self.error(format!("excess elements in '{}' initializer", ty));
+				}
+				ASTNode::InitList(members.iter().zip(values).map(|(member, value)| self.initializer(value, &member.ty)).collect())
+			}
+			(_, ASTNode::InitList(mut values)) if values.len() == 1 => self.initializer(values.remove(0), ty),
+			(_, ASTNode::InitList(values)) => {
+				let problem = if values.is_empty() { "empty" } else { "excess elements in" };
+				self.error(format!("{} scalar initializer", problem));
+				ASTNode::InitList(Vec::new())
+			}
+			(_, node) => {
+				let (node, from) = self.expr(node);
+				self.convert(node, from, ty, "initialization")
+			}
+		}
+	}
+
+	/// Checks a function call, converting arguments to the parameter types
+	fn call(&mut self, name: String, args: Vec<ASTNode>) -> (ASTNode, Type) {
+		let (return_type, params) = match self.lookup(&name) {
+			Type::Function { return_type, params } => (*return_type, params),
+			_ => (Type::Int, Vec::new()),
+		};
+		let args = args.into_iter().enumerate().map(|(i, arg)| {
+			let (arg, ty) = self.expr(arg);
+			match params.get(i) {
+				Some(param) => {
+					let context = format!("passing argument {} of '{}'", i + 1, name);
+					self.convert(arg, ty, param, &context)
+				}
+				None => arg, // variadic arguments keep their own type
+			}
+		}).collect();
+		(ASTNode::FuncCall { name, args }, return_type)
+	}
+
+	/// Multiplies an integer operand by the size of a pointer's target
+	fn scale(node: ASTNode, size: usize) -> ASTNode {
+		if size == 1 {
+			node
+		} else {
+			ASTNode::BinaryOp { op: Token::Mul, left: Box::new(node), right: Box::new(ASTNode::Num(size as i64)) }
+		}
+	}
+
+	/// Checks an expression, returning the rewritten node and its type
+	fn expr(&mut self, node: ASTNode) -> (ASTNode, Type) {
+		match node {
+			ASTNode::Num(n) => (ASTNode::Num(n), Type::Int),
+			ASTNode::FNum(n) => (ASTNode::FNum(n), Type::Double),
+			ASTNode::Str(s) => (ASTNode::Str(s), Type::Char.pointer_to()),
+			ASTNode::Id(name) => {
+				let ty = self.lookup(&name);
+				(ASTNode::Id(name), ty)
+			}
+			ASTNode::FuncCall { name, args } => {
+				let (node, ty) = self.call(name, args);
+				if ty == Type::Void {
+					self.error("void value not ignored as it ought to be".to_string());
+				}
+				(node, ty)
+			}
+			ASTNode::SizeofType(typename) => {
+				self.complete(&typename, &format!("invalid application of 'sizeof' to incomplete type '{}'", typename));
+				(ASTNode::Num(typename.size() as i64), Type::Int)
+			}
+			ASTNode::SizeofExpr(expr) => {
+				// Only the operand's type matters; its code is dropped
+				let (_, ty) = self.expr(*expr);
+				self.complete(&ty, &format!("invalid application of 'sizeof' to incomplete type '{}'", ty));
+				(ASTNode::Num(ty.size() as i64), Type::Int)
+			}
+			ASTNode::Cast { typename, expr } => {
+				let (expr, from) = self.expr(*expr);
+				if typename != Type::Void && !is_scalar(&typename) {
+					self.error(format!("conversion to non-scalar type '{}' requested", typename));
+				} else if typename != Type::Void && !is_scalar(&decay(from.clone())) {
+					let message = if from == Type::Void {
+						"void value not ignored as it ought to be".to_string()
+					} else {
+						format!("invalid cast from '{}' to '{}'", from, typename)
+					};
+					self.error(message);
+				} else if (typename.target().is_some() && from.is_floating())
+					|| (typename.is_floating() && decay(from.clone()).target().is_some()) {
+					// Pointers and floating values do not convert into each other
+					self.error(format!("invalid cast from '{}' to '{}'", from, typename));
+				}
+				(ASTNode::Cast { typename: typename.clone(), expr: Box::new(expr) }, typename)
+			}
+			ASTNode::Index { array, index } => {
+				let (array, array_type) = self.expr(*array);
+				let (index, index_type) = self.expr(*index);
+				let elem = match array_type.target() {
+					Some(elem) => elem.clone(),
+					None => {
+						self.error("pointer type expected".to_string());
+						Type::Int
+					}
+				};
+				if !is_integer(&index_type) {
+					self.error("array subscript is not an integer".to_string());
+				}
+				(ASTNode::Index { array: Box::new(array), index: Box::new(index) }, elem)
+			}
+			ASTNode::Member { expr, name } => {
+				let (expr, ty) = self.expr(*expr);
+				let member_type = match &ty {
+					Type::Struct(aggregate) => match aggregate.member(&name) {
+						Some(member) => member.ty,
+						None if !aggregate.is_complete() => {
+							self.error(format!("invalid use of incomplete type '{}'", ty));
+							Type::Int
+						}
+						None => {
+							self.error(format!("'{}' has no member named '{}'", ty, name));
+							Type::Int
+						}
+					},
+					_ => {
+						self.error(format!("request for member '{}' in something not a structure or union", name));
+						Type::Int
+					}
+				};
+				(ASTNode::Member { expr: Box::new(expr), name }, member_type)
+			}
+			ASTNode::Assign { name, value } => {
+				let target = self.lookup(&name);
+				let (value, from) = self.expr(*value);
+				let value = match target {
+					Type::Array(..) | Type::Function { .. } => {
+						self.error("bad lvalue in assignment".to_string());
+						value
+					}
+					_ => self.convert(value, from, &target, "assignment"),
+				};
+				(ASTNode::Assign { name, value: Box::new(value) }, target)
+			}
+			ASTNode::MemAssign { target, value } => {
+				let (target, target_type) = self.expr(*target);
+				let (value, from) = self.expr(*value);
+				let value = if let Type::Array(..) = target_type {
+					self.error("bad lvalue in assignment".to_string());
+					value
+				} else {
+					self.convert(value, from, &target_type, "assignment")
+				};
+				(ASTNode::MemAssign { target: Box::new(target), value: Box::new(value) }, target_type)
+			}
+			ASTNode::Cond { cond, then_branch, else_branch } => {
+				let cond = self.condition(*cond);
+				let (then_branch, then_type) = self.expr(*then_branch);
+				let (else_branch, else_type) = self.expr(*else_branch);
+				let (then_type, else_type) = (decay(then_type), decay(else_type));
+				let (then_branch, else_branch, ty) = if is_arithmetic(&then_type) && is_arithmetic(&else_type) {
+					let ty = usual_arithmetic(&then_type, &else_type);
+					(Self::cast(then_branch, &then_type, &ty), Self::cast(else_branch, &else_type, &ty), ty)
+				} else if then_type.target().is_some() && (else_type == then_type || is_null(&else_branch)) {
+					(then_branch, else_branch, then_type)
+				} else if else_type.target().is_some() && is_null(&then_branch) {
+					(then_branch, else_branch, else_type)
+				} else {
+					if then_type != else_type {
+						self.warning(format!("type mismatch in conditional expression ('{}' and '{}')", then_type, else_type));
+					}
+					(then_branch, else_branch, then_type)
+				};
+				(ASTNode::Cond {
+					cond: Box::new(cond),
+					then_branch: Box::new(then_branch),
+					else_branch: Box::new(else_branch),
+				}, ty)
+			}
+			ASTNode::UnaryOp { op, expr } => self.unary(op, *expr),
+			ASTNode::PostfixOp { op, expr } => {
+				let lvalue = is_lvalue(&expr);
+				let (expr, ty) = self.expr(*expr);
+				if !lvalue || !(is_arithmetic(&ty) || matches!(ty, Type::Pointer(_))) {
+					self.error("bad lvalue in post-increment".to_string());
+				}
+				(ASTNode::PostfixOp { op, expr: Box::new(expr) }, ty)
+			}
+			ASTNode::BinaryOp { op: Token::Comma, left, right } => {
+				// The left value is discarded, so it may be void
+				let left = self.stmt(*left);
+				let (right, ty) = self.expr(*right);
+				(ASTNode::BinaryOp { op: Token::Comma, left: Box::new(left), right: Box::new(right) }, ty)
+			}
+			ASTNode::BinaryOp { op, left, right } => self.binary(op, *left, *right),
+			other => (other, Type::Void),
+		}
+	}
+
+	/// Checks a unary operation
+	fn unary(&mut self, op: Token, expr: ASTNode) -> (ASTNode, Type) {
+		let lvalue = is_lvalue(&expr);
+		let (expr, ty) = self.expr(expr);
+		let result = match op {
+			Token::Mul => match ty.target() {
+				Some(target) if *target != Type::Void => target.clone(),
+				_ => {
+					self.error("bad dereference".to_string());
+					Type::Int
+				}
+			},
+			Token::And => {
+				if !lvalue {
+					self.error("bad address-of".to_string());
+				}
+				ty.pointer_to()
+			}
+			Token::Not => {
+				if !is_scalar(&ty) {
+					self.error(format!("wrong type argument to unary '!' ('{}')", ty));
+				}
+				let expr = Self::truth(expr, &ty);
+				return (ASTNode::UnaryOp { op, expr: Box::new(expr) }, Type::Int);
+			}
+			Token::Inc | Token::Dec => {
+				if !lvalue || !(is_arithmetic(&ty) || matches!(ty, Type::Pointer(_))) {
+					self.error("bad lvalue in pre-increment".to_string());
+				}
+				ty
+			}
+			Token::Tilde => {
+				if !is_integer(&ty) {
+					self.error(format!("wrong type argument to bit-complement ('{}')", ty));
+				}
+				Type::Int
+			}
+			_ => {
+				if !is_arithmetic(&ty) {
+					self.error(format!("wrong type argument to unary '{}' ('{}')", op_symbol(&op), ty));
+				}
+				usual_arithmetic(&ty, &ty)
+			}
+		};
+		(ASTNode::UnaryOp { op, expr: Box::new(expr) }, result)
+	}
+
+	/// Checks a binary operation, applying conversions and pointer scaling
+	fn binary(&mut self, op: Token, left: ASTNode, right: ASTNode) -> (ASTNode, Type) {
+		let (left, left_type) = self.expr(left);
+		let (right, right_type) = self.expr(right);
+		let (left_type, right_type) = (decay(left_type), decay(right_type));
+		let node = |op: Token, left: ASTNode, right: ASTNode| ASTNode::BinaryOp {
+			op,
+			left: Box::new(left),
+			right: Box::new(right),
+		};
+
+		// Pointer arithmetic
+		match (&op, left_type.target(), right_type.target()) {
+			(Token::Add | Token::Sub, Some(target), None) if is_integer(&right_type) => {
+				let right = Self::scale(right, target.size());
+				return (node(op, left, right), left_type);
+			}
+			(Token::Add, None, Some(target)) if is_integer(&left_type) => {
+				let left = Self::scale(left, target.size());
+				return (node(op, left, right), right_type);
+			}
+			(Token::Sub, Some(left_target), Some(right_target)) if left_target == right_target => {
+				// The difference counts elements, not bytes
+				let size = left_target.size() as i64;
+				let diff = node(Token::Sub, left, right);
+				let diff = if size == 1 { diff } else { node(Token::Div, diff, ASTNode::Num(size)) };
+				return (diff, Type::Int);
+			}
+			_ => {}
+		}
+
+		let invalid = |this: &mut Self| {
+			this.error(format!(
+				"invalid operands to binary {} ('{}' and '{}')",
+				op_symbol(&op), left_type, right_type
+			));
+		};
+
+		match op {
+			Token::Eq | Token::Ne | Token::Lt | Token::Gt | Token::Le | Token::Ge => {
+				if is_arithmetic(&left_type) && is_arithmetic(&right_type) {
+					let common = usual_arithmetic(&left_type, &right_type);
+					let left = Self::cast(left, &left_type, &common);
+					let right = Self::cast(right, &right_type, &common);
+					return (node(op, left, right), Type::Int);
+				}
+				if !is_scalar(&left_type) || !is_scalar(&right_type) {
+					invalid(self);
+					return (node(op, left, right), Type::Int);
+				}
+				match (left_type.target(), right_type.target()) {
+					(Some(l), Some(r)) if l != r && *l != Type::Void && *r != Type::Void => {
+						self.warning("comparison of distinct pointer types lacks a cast".to_string());
+					}
+					(Some(_), None) if !is_null(&right) => {
+						self.warning("comparison between pointer and integer".to_string());
+					}
+					(None, Some(_)) if !is_null(&left) => {
+						self.warning("comparison between pointer and integer".to_string());
+					}
+					_ => {}
+				}
+				(node(op, left, right), Type::Int)
+			}
+			Token::Lan | Token::Lor => {
+				if !is_scalar(&left_type) || !is_scalar(&right_type) {
+					invalid(self);
+				}
+				(node(op, Self::truth(left, &left_type), Self::truth(right, &right_type)), Type::Int)
+			}
+			Token::Mod | Token::And | Token::Or | Token::Xor | Token::Shl | Token::Shr => {
+				if !is_integer(&left_type) || !is_integer(&right_type) {
+					invalid(self);
+				}
+				(node(op, left, right), Type::Int)
+			}
+			_ => {
+				if !is_arithmetic(&left_type) || !is_arithmetic(&right_type) {
+					invalid(self);
+					return (node(op, left, right), Type::Int);
+				}
+				let common = usual_arithmetic(&left_type, &right_type);
+				let left = Self::cast(left, &left_type, &common);
+				let right = Self::cast(right, &right_type, &common);
+				(node(op, left, right), common)
+			}
+		}
+	}
+}
+
+impl Default for TypeChecker {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,201 @@
+// Types file
+
+/*
+C types attached to declarations in the AST.
+Shared by the parser (which builds them) and the code generator
+(which uses them for sizes, pointer scaling and load/store width).
+*/
+
+use crate::lexer::Token;
+use crate::vm::WORD_SIZE;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Represents a C type
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+	Int,
+	Char,
+	Void,
+	Float,
+	Double,
+	Pointer(Box<Type>),      // T*, nested for T**, T***...
+	Array(Box<Type>, usize), // T[n]
+	Function {
+		return_type: Box<Type>,
+		params: Vec<Type>,
+	},
+	Struct(Rc<Aggregate>),   // struct or union, shared by every use of its tag
+}
+
+/// A struct or union member at a fixed byte offset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+	pub name: String,
+	pub ty: Type,
+	pub offset: usize,
+}
+
+/// The members and size of a complete struct or union
+struct Layout {
+	members: Vec<Member>,
+	size: usize,
+	align: usize,
+}
+
+/// A struct or union definition
+/// The layout is filled in once the body is seen, so members like
+/// `struct node *next` can refer to the struct being defined
+pub struct Aggregate {
+	pub tag: Option<String>,
+	pub is_union: bool,
+	layout: RefCell<Option<Layout>>,
+}
+
+impl Aggregate {
+	/// Creates an incomplete struct or union
+	pub fn new(tag: Option<String>, is_union: bool) -> Rc<Aggregate> {
+		Rc::new(Aggregate { tag, is_union, layout: RefCell::new(None) })
+	}
+
+	/// Lays out the members, each aligned to its own alignment
+	/// Union members all start at offset 0; a duplicate name leaves the type incomplete
+	pub fn define(&self, members: Vec<(Type, String)>) -> Result<(), String> {
+		let (mut size, mut align): (usize, usize) = (0, 1);
+		let mut laid_out: Vec<Member> = Vec::new();
+		for (ty, name) in members {
+			if laid_out.iter().any(|member| member.name == name) {
+				return Err(format!("Duplicate member '{}' in '{}'", name, self));
+			}
+			align = align.max(ty.align());
+			let offset = if self.is_union { 0 } else { size.next_multiple_of(ty.align()) };
+			size = if self.is_union { size.max(ty.size()) } else { offset + ty.size() };
+			laid_out.push(Member { name, ty, offset });
+		}
+		let size = size.next_multiple_of(align);
+		*self.layout.borrow_mut() = Some(Layout { members: laid_out, size, align });
+		Ok(())
+	}
+
+	/// Whether the body has been seen
+	pub fn is_complete(&self) -> bool {
+		self.layout.borrow().is_some()
+	}
+
+	/// Looks up a member by name
+	pub fn member(&self, name: &str) -> Option<Member> {
+		let layout = self.layout.borrow();
+		layout.as_ref()?.members.iter().find(|member| member.name == name).cloned()
+	}
+
+	/// All members in declaration order
+	pub fn members(&self) -> Vec<Member> {
+		self.layout.borrow().as_ref().map(|layout| layout.members.clone()).unwrap_or_default()
+	}
+}
+
+/// Two struct types are the same only if they come from the same definition
+impl PartialEq for Aggregate {
+	fn eq(&self, other: &Self) -> bool {
+		std::ptr::eq(self, other)
+	}
+}
+
+/// Printed by name only, since members may point back to the struct itself
+impl fmt::Debug for Aggregate {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self)
+	}
+}
+
+impl fmt::Display for Aggregate {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let keyword = if self.is_union { "union" } else { "struct" };
+		match &self.tag {
+			Some(tag) => write!(f, "{} {}", keyword, tag),
+			None => write!(f, "{} <anonymous>", keyword),
+		}
+	}
+}
+
+impl Type {
+	/// Maps a base type keyword to its type
+	/// `short` and `long` share the word-sized int representation
+	pub fn from_token(token: &Token) -> Option<Type> {
+		match token {
+			Token::Int | Token::Short | Token::Long => Some(Type::Int),
+			Token::Char => Some(Type::Char),
+			Token::Void => Some(Type::Void),
+			Token::Float => Some(Type::Float),
+			Token::Double => Some(Type::Double),
+			_ => None,
+		}
+	}
+
+	/// Returns a pointer to this type
+	pub fn pointer_to(self) -> Type {
+		Type::Pointer(Box::new(self))
+	}
+
+	/// Returns the pointed-to type of a pointer, or the element type of an array
+	pub fn target(&self) -> Option<&Type> {
+		match self {
+			Type::Pointer(inner) | Type::Array(inner, _) => Some(inner),
+			_ => None,
+		}
+	}
+
+	/// Whether values of this type live in memory and evaluate to their address
+	pub fn is_aggregate(&self) -> bool {
+		matches!(self, Type::Array(..) | Type::Struct(_))
+	}
+
+	/// Whether values of this type are floating point
+	pub fn is_floating(&self) -> bool {
+		matches!(self, Type::Float | Type::Double)
+	}
+
+	/// Size in bytes of a value of this type; 0 for an incomplete struct
+	pub fn size(&self) -> usize {
+		match self {
+			Type::Char => 1,
+			Type::Void => 1, // lets `void *` arithmetic step by bytes, as in gcc
+			Type::Float => 4,
+			Type::Double => 8,
+			Type::Array(elem, n) => elem.size() * n,
+			Type::Int | Type::Pointer(_) | Type::Function { .. } => WORD_SIZE,
+			Type::Struct(aggregate) => aggregate.layout.borrow().as_ref().map_or(0, |layout| layout.size),
+		}
+	}
+
+	/// Alignment in bytes required by values of this type
+	pub fn align(&self) -> usize {
+		match self {
+			Type::Array(elem, _) => elem.align(),
+			Type::Struct(aggregate) => aggregate.layout.borrow().as_ref().map_or(1, |layout| layout.align),
+			other => other.size(),
+		}
+	}
+}
+
+impl fmt::Display for Type {
+	/// Writes the type as it would be spelled in C, e.g. `char **`
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Type::Int => write!(f, "int"),
+			Type::Char => write!(f, "char"),
+			Type::Void => write!(f, "void"),
+			Type::Float => write!(f, "float"),
+			Type::Double => write!(f, "double"),
+			Type::Pointer(inner) if matches!(**inner, Type::Pointer(_)) => write!(f, "{}*", inner),
+			Type::Pointer(inner) => write!(f, "{} *", inner),
+			Type::Array(elem, n) => write!(f, "{}[{}]", elem, n),
+			Type::Function { return_type, params } => {
+				let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+				write!(f, "{} ({})", return_type, params.join(", "))
+			}
+			Type::Struct(aggregate) => write!(f, "{}", aggregate),
+		}
+	}
+}